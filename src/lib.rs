@@ -0,0 +1,23 @@
+//! Interactive search and replace across files.
+//!
+//! The engine behind the `rplc` binary: list files from a glob, plan the
+//! changes of a query, preview them and write them back.
+//!
+//! ```
+//! use rplc::{FileSource, Replacer};
+//!
+//! let replacer = Replacer::new("fn main", "fn start").max_filesize(1024 * 1024);
+//! let plan = replacer.plan(&FileSource::Glob("src/main.rs".to_string())).unwrap();
+//! for file in &plan.files {
+//!     println!("{}: {} changes", file.path, file.changes);
+//! }
+//! ```
+
+pub(crate) mod libs;
+
+pub use libs::cli::run;
+pub use libs::decorate_file_content::DiffStyle;
+pub use libs::replacer::{FilePlan, FileSource, Replacer, SearchPlan};
+pub use libs::search::SearchOptions;
+pub use libs::syntax_highlight::SyntaxHighlighter;
+pub use libs::theme::ColorDepth;
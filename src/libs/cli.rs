@@ -0,0 +1,544 @@
+use std::fmt::Display;
+use std::io::{stdout, IsTerminal};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use clap::{Parser, Subcommand};
+use crossterm::event::{
+    poll, read, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste,
+    EnableMouseCapture, Event,
+};
+use crossterm::execute;
+use crossterm::style::{force_color_output, Color, Stylize};
+
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, Clear, ClearType, EnterAlternateScreen,
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+use super::config::{Config, DEFAULT_GLOB};
+use super::decorate_file_content::DiffStyle;
+use super::file_store::parse_size;
+use super::history::History;
+use super::keymap::{Action, Keymap};
+use super::prompt::compact_line;
+use super::renderer::{basic_colors, strip_styles, CrosstermRenderer, Renderer};
+use super::replacer::{FileSource, Replacer, SearchPlan};
+use super::screen::{
+    print_help, print_prompt, print_status, render_loading, render_results, render_screen,
+};
+use super::search::{run_search, ScanProgress, SearchMessage, SearchOptions};
+use super::split_query::file_type_extensions;
+use super::state::AppState;
+use super::terminal::{get_screen_size, hide_cursor, show_cursor, ScreenSize};
+use super::theme::{ColorDepth, ColorMode, Palette};
+
+/// Quiet period after the last keystroke before a search is started.
+const DEBOUNCE: Duration = Duration::from_millis(80);
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
+struct Opts {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(help = "Glob pattern to search for files")]
+    glob: Option<String>,
+
+    #[arg(help = "Query to search for")]
+    query: Option<String>,
+
+    #[arg(help = "Substitute to replace query with")]
+    substitute: Option<String>,
+
+    #[arg(short, long, help = "Write changes to files")]
+    write: bool,
+
+    #[arg(long, conflicts_with = "write", help = "Ask before writing, even if the config says otherwise")]
+    confirm: bool,
+
+    #[arg(short, long, help = "Classic mode")]
+    classic: bool,
+
+    #[arg(long, help = "Match line breaks in the query against both LF and CRLF")]
+    eol_insensitive: bool,
+
+    #[arg(long, value_parser = parse_size, help = "Skip files larger than this size (e.g. 512K, 10M)")]
+    max_filesize: Option<u64>,
+
+    #[arg(long, help = "Type the query on a single line: <glob> <query> <replacement>")]
+    compact: bool,
+
+    #[arg(short = 't', long = "type", help = "Only search files of this type (e.g. rust, py), can be repeated")]
+    types: Vec<String>,
+
+    #[arg(short = 'C', long, help = "Unchanged lines to show around changes")]
+    context: Option<usize>,
+
+    #[arg(long, help = "Syntax highlighting theme of the previews (e.g. Nord, base16-ocean.dark)")]
+    theme: Option<String>,
+
+    #[arg(long, value_name = "STYLE", value_parser = DiffStyle::from_str, help = "Show replacements as compact or two-line diffs")]
+    diff: Option<DiffStyle>,
+
+    #[arg(
+        long,
+        value_name = "WHEN",
+        default_value = "auto",
+        value_parser = ColorMode::from_str,
+        help = "Colour the output: auto, always or never (auto follows NO_COLOR)"
+    )]
+    color: ColorMode,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    #[command(about = "List the queries run before, oldest first")]
+    History {
+        #[arg(short = 'n', long, help = "Only list this many of the latest queries")]
+        limit: Option<usize>,
+    },
+}
+
+/// Search options of the configuration, overridden by the command line.
+fn search_options(opts: &Opts, config: &Config) -> Result<SearchOptions, std::io::Error> {
+    let mut options = config.search_options()?;
+    options.eol_insensitive |= opts.eol_insensitive;
+    if let Some(max_filesize) = opts.max_filesize {
+        options.max_filesize = max_filesize;
+    }
+    if let Some(context) = opts.context {
+        options.context = context;
+    }
+    if let Some(diff) = opts.diff {
+        options.diff = diff;
+    }
+    if !opts.types.is_empty() {
+        options.extensions.clear();
+    }
+    for name in &opts.types {
+        let extensions = file_type_extensions(name).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("unknown file type {}", name),
+            )
+        })?;
+        options.extensions.extend(extensions);
+    }
+    Ok(options)
+}
+
+/// History of the XDG state directory, kept in memory only without a home
+/// directory.
+fn load_history() -> Result<History, std::io::Error> {
+    match History::default_path() {
+        Some(path) => History::load(&path),
+        None => Ok(History::default()),
+    }
+}
+
+/// `text` as printed: without its styles when colours are off, in the
+/// colours of the 16 on terminals that have no others.
+fn styled(text: impl Display, colors: Option<ColorDepth>) -> String {
+    match colors {
+        Some(ColorDepth::Ansi16) => basic_colors(&text.to_string()),
+        Some(_) => text.to_string(),
+        None => strip_styles(&text.to_string()),
+    }
+}
+
+fn print_history(limit: Option<usize>, colors: Option<ColorDepth>) -> Result<(), std::io::Error> {
+    let history = load_history()?;
+    let entries = history.entries();
+    let skip = entries.len().saturating_sub(limit.unwrap_or(entries.len()));
+    for entry in &entries[skip..] {
+        let written = match entry.written {
+            true => "written".stylize().with(Color::Green),
+            false => "       ".stylize(),
+        };
+        let at = entry.at.format("%Y-%m-%d %H:%M").to_string();
+        let line = format!("{}  {}  {}", at.with(Color::DarkGrey), written, entry.query);
+        println!("{}", styled(line, colors));
+    }
+    Ok(())
+}
+
+fn prompt_user(plan: &SearchPlan, colors: Option<ColorDepth>) -> bool {
+    let found = format!(
+        "\nFound {} replacements in {} files.",
+        plan.replaced_count()
+            .to_string()
+            .stylize()
+            .with(Color::Green),
+        plan.file_count().to_string().stylize().with(Color::Yellow),
+    );
+    println!("{}", styled(found, colors));
+    loop {
+        println!("\nDo you want to continue? [y/n]");
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input).unwrap();
+        match input.trim() {
+            "y" => return true,
+            "n" => return false,
+            _ => println!("Invalid input. Please enter 'y' or 'n'"),
+        }
+    }
+}
+
+fn print_skipped(plan: &SearchPlan, colors: Option<ColorDepth>) {
+    for file in &plan.skipped {
+        let skipped = format!("Skipping {}: larger than --max-filesize", file);
+        println!("{}", styled(skipped.with(Color::DarkGrey), colors));
+    }
+}
+
+async fn classic_mode(
+    opts: &Opts,
+    options: &SearchOptions,
+    colors: Option<ColorDepth>,
+) -> Result<(), std::io::Error> {
+    if opts.query.is_none() || opts.substitute.is_none() || opts.glob.is_none() {
+        println!("Invalid input. Please enter <GLOB> <QUERY> <SUBSTITUTE>");
+        return Ok(());
+    }
+    let query = &opts.query;
+    let substitute = &opts.substitute;
+    let glob = opts.glob.clone();
+
+    let header = format!(
+        "rplc {} with {} in {}:\n",
+        query
+            .clone()
+            .unwrap_or("".to_string())
+            .stylize()
+            .with(crossterm::style::Color::Yellow)
+            .bold(),
+        substitute
+            .clone()
+            .unwrap_or("".to_string())
+            .stylize()
+            .with(crossterm::style::Color::Green)
+            .bold(),
+        &glob
+            .clone()
+            .unwrap_or("".to_string())
+            .stylize()
+            .with(crossterm::style::Color::Green)
+    );
+    println!("{}", styled(header, colors));
+
+    let glob = glob.unwrap_or("".to_string());
+
+    // Without colours, removed and added text only tell apart on their own
+    // lines.
+    let mut options = options.clone();
+    if colors.is_none() {
+        options.diff = DiffStyle::TwoLine;
+    }
+    let replacer = Replacer::new(
+        query.as_deref().unwrap_or(""),
+        substitute.as_deref().unwrap_or(""),
+    )
+    .options(options);
+    let plan = match replacer.plan(&FileSource::Glob(glob)) {
+        Ok(plan) => plan,
+        Err(e) => {
+            println!("Could not list files: {}", e);
+            SearchPlan::default()
+        }
+    };
+    print_skipped(&plan, colors);
+    for file in &plan.files {
+        for line in &file.preview {
+            println!("{}", styled(line, colors));
+        }
+    }
+
+    let replaced = (opts.write || prompt_user(&plan, colors)).then(|| replacer.write(&plan));
+    // Recorded as written only once the files are.
+    let written = replaced.as_ref().is_some_and(|replaced| replaced.is_ok());
+    let query = compact_line(&[
+        opts.glob.clone(),
+        opts.query.clone(),
+        opts.substitute.clone(),
+    ]);
+    if let Err(e) = load_history().and_then(|mut history| history.push(&query, written)) {
+        println!("Could not save the query to the history: {}", e);
+    }
+    match replaced {
+        Some(replaced) => println!("{} replacements were made.", replaced?),
+        None => println!("No changes were made."),
+    }
+    Ok(())
+}
+
+/// Switches to the alternate screen, in raw mode, with mouse and paste
+/// events.
+fn enter_screen() -> Result<(), std::io::Error> {
+    hide_cursor()?;
+    execute!(
+        stdout(),
+        EnterAlternateScreen,
+        EnableMouseCapture,
+        EnableBracketedPaste
+    )?;
+    execute!(stdout(), Clear(ClearType::All))?;
+    enable_raw_mode()
+}
+
+fn leave_screen() -> Result<(), std::io::Error> {
+    disable_raw_mode()?;
+    execute!(
+        stdout(),
+        DisableMouseCapture,
+        DisableBracketedPaste,
+        crossterm::terminal::LeaveAlternateScreen
+    )?;
+    show_cursor()
+}
+
+/// Opens the first file in view in `$VISUAL` or `$EDITOR` (`vi` without
+/// either) at its first change in view, then draws the screen again.
+fn open_in_editor(
+    out: &mut CrosstermRenderer,
+    state: &mut AppState,
+) -> Result<(), std::io::Error> {
+    let (path, line) = match state.editor_target() {
+        Some(target) => target,
+        None => return Ok(()),
+    };
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or("vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+
+    leave_screen()?;
+    let status = std::process::Command::new(program)
+        .args(words)
+        .arg(format!("+{}", line))
+        .arg(&path)
+        .status();
+    enter_screen()?;
+    state.notice = match status {
+        Ok(status) if status.success() => None,
+        Ok(status) => Some(format!("{} exited with {}", program, status)),
+        Err(e) => Some(format!("Could not run {}: {}", program, e)),
+    };
+    // The file may have changed, and the terminal with it.
+    state.invalidate_search();
+    state.resize(get_screen_size()?);
+    out.resize(state.screen);
+    render_screen(out, state)
+}
+
+async fn interactive_mode(
+    query: &str,
+    options: SearchOptions,
+    compact: bool,
+    keymap: Keymap,
+    palette: Palette,
+    colored: bool,
+) -> Result<(), std::io::Error> {
+    enter_screen()?;
+    let mut state = AppState::new(query, options, get_screen_size()?);
+    state.prompt.compact = compact;
+    state.history = load_history().unwrap_or_default();
+    state.keymap = keymap;
+    state.palette = palette;
+    let mut out = CrosstermRenderer::new(state.screen)
+        .colored(colored)
+        .depth(state.options.color_depth);
+    let (sender, mut receiver) = unbounded_channel();
+    handle_user_query_with_errors(&mut out, &mut state, &sender);
+    let mut typed_at: Option<Instant> = None;
+    loop {
+        while let Ok(message) = receiver.try_recv() {
+            if state.apply(message) {
+                render_results(&mut out, &state)?;
+            }
+        }
+        if state.receive_pane() {
+            render_results(&mut out, &state)?;
+        }
+        if state.searching() {
+            print_status(&mut out, &state)?;
+        }
+        let timeout = match typed_at {
+            Some(typed_at) => DEBOUNCE.saturating_sub(typed_at.elapsed()),
+            None if state.searching() || state.rendering_pane() => Duration::from_millis(20),
+            None => Duration::from_millis(500),
+        };
+        out.flush()?;
+        if poll(timeout)? {
+            match read()? {
+                Event::Key(event) if state.handle_history_search_key(event) => {
+                    print_prompt(&mut out, &state)?;
+                    typed_at = Some(Instant::now());
+                }
+                Event::Key(event) if state.handle_theme_picker_key(event) => {
+                    render_results(&mut out, &state)?;
+                    typed_at = Some(Instant::now());
+                }
+                Event::Key(event) => match state.keymap.action(event) {
+                    Some(Action::Quit) if !state.show_help => {
+                        state.remember_search(true);
+                        leave_screen()?;
+                        println!("Exiting...");
+                        return Ok(());
+                    }
+                    Some(Action::OpenInEditor) => {
+                        open_in_editor(&mut out, &mut state)?;
+                        typed_at = Some(Instant::now());
+                    }
+                    Some(action) => {
+                        if state.perform(action) {
+                            print_prompt(&mut out, &state)?;
+                            render_results(&mut out, &state)?;
+                        }
+                        let searches = [Action::Write, Action::Undo, Action::ToggleDiff];
+                        if action.edits_query() || searches.contains(&action) {
+                            typed_at = Some(Instant::now());
+                        }
+                    }
+                    None => {
+                        let before = state.prompt.editor().text().to_string();
+                        if state.prompt.editor_mut().handle_key(event) {
+                            print_prompt(&mut out, &state)?;
+                        }
+                        if state.prompt.editor().text() != before {
+                            state.notice = None;
+                            typed_at = Some(Instant::now());
+                        }
+                    }
+                },
+                Event::Resize(width, height) => {
+                    state.resize(ScreenSize {
+                        width: width as usize,
+                        height: height as usize,
+                    });
+                    out.resize(state.screen);
+                    render_screen(&mut out, &state)?;
+                }
+                Event::Mouse(event) if state.handle_mouse(event) => {
+                    render_results(&mut out, &state)?;
+                }
+                Event::Paste(text) => {
+                    state.prompt.editor_mut().insert(&text);
+                    print_prompt(&mut out, &state)?;
+                    typed_at = Some(Instant::now());
+                }
+                _ => (),
+            }
+        } else if typed_at.take().is_some() {
+            // The user stopped typing: search for the latest query only.
+            handle_user_query_with_errors(&mut out, &mut state, &sender);
+        }
+    }
+}
+
+fn handle_user_query_with_errors(
+    out: &mut dyn Renderer,
+    state: &mut AppState,
+    sender: &UnboundedSender<SearchMessage>,
+) {
+    if let Err(e) = handle_user_query(out, state, sender) {
+        state.last_error = Some(e.to_string());
+        let _ = print_status(out, state);
+    }
+}
+
+/// Starts a background search for the current query. Its outcome is sent
+/// back through `sender`, tagged with a generation so that the event loop
+/// drops the results of superseded searches.
+fn handle_user_query(
+    out: &mut dyn Renderer,
+    state: &mut AppState,
+    sender: &UnboundedSender<SearchMessage>,
+) -> Result<(), std::io::Error> {
+    print_help(out, state)?;
+    print_prompt(out, state)?;
+    let split = state.split();
+
+    if !state.needs_search(&split) {
+        return Ok(());
+    }
+    let generation = state.next_generation();
+    render_loading(out, state)?;
+
+    let glob = match split.glob {
+        Some(glob) => glob,
+        None => {
+            state.cancel_search();
+            return render_results(out, state);
+        }
+    };
+    let listing = state.files.cached_glob(&glob);
+    let contents = state.files.contents();
+    let options = split.modifiers.apply(&state.options);
+    let progress = Arc::new(ScanProgress::new(listing.is_some()));
+    state.progress = progress.clone();
+    let sender = sender.clone();
+    let task = tokio::task::spawn(async move {
+        let (search, replace) = (split.search, split.replace);
+        let outcome = run_search(&glob, listing, search, replace, &contents, &options, &progress)
+            .await
+            .map_err(|e| e.to_string());
+        let _ = sender.send(SearchMessage {
+            generation,
+            glob,
+            outcome,
+        });
+    });
+    state.start_search(task);
+
+    Ok(())
+}
+
+/// Runs `rplc` with the arguments of the process.
+pub async fn run() -> Result<(), std::io::Error> {
+    let mut opts: Opts = Opts::parse();
+    let colored = opts.color.colored(stdout().is_terminal());
+    let depth = ColorDepth::detect();
+    let colors = colored.then_some(depth);
+    // Colours are left out by rplc itself, NO_COLOR included.
+    force_color_output(true);
+    if let Some(Command::History { limit }) = opts.command {
+        return print_history(limit, colors);
+    }
+
+    let loaded = Config::load().and_then(|mut config| {
+        config.theme = opts.theme.clone().or(config.theme);
+        let mut options = search_options(&opts, &config)?;
+        options.theme = Some(config.theme()?);
+        options.color_depth = depth;
+        Ok((options, config.keymap()?, config.palette(depth)?, config))
+    });
+    let (mut options, keymap, palette, config) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("rplc: {}", e);
+            std::process::exit(2);
+        }
+    };
+    opts.write = !opts.confirm && (opts.write || config.write == Some(true));
+
+    if opts.classic {
+        return classic_mode(&opts, &options, colors).await;
+    }
+
+    let glob = opts.glob.clone().or(config.glob);
+    let query = compact_line(&[
+        Some(glob.unwrap_or(DEFAULT_GLOB.to_string())),
+        opts.query.clone(),
+        opts.substitute.clone(),
+    ]);
+    let compact = opts.compact || config.compact == Some(true);
+    if !colored {
+        options.theme = None;
+    }
+    interactive_mode(&query, options, compact, keymap, palette, colored).await
+}
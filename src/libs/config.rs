@@ -0,0 +1,350 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use glob::Pattern;
+use serde::Deserialize;
+
+#[cfg(test)]
+use super::decorate_file_content::DiffStyle;
+use super::file_store::parse_size;
+use super::keymap::{Action, KeyBinding, Keymap};
+use super::search::SearchOptions;
+use super::split_query::file_type_extensions;
+use super::syntax_highlight::{theme_names, DEFAULT_THEME};
+#[cfg(test)]
+use super::temp_dir::TempDir;
+use super::theme::{ColorDepth, Palette};
+
+/// Name of the project configuration, looked up from the current directory
+/// to the root.
+pub const PROJECT_CONFIG: &str = ".rplc.toml";
+/// Glob of the first search when neither the command line nor the
+/// configuration give one.
+pub const DEFAULT_GLOB: &str = "src/**/*";
+
+/// Settings of `config.toml` files. Every key is optional:
+///
+/// ```toml
+/// glob = "crates/**/*.rs"
+/// ignore = ["target/**", "*.lock"]
+/// types = ["rust", "toml"]
+/// theme = "base16-ocean.dark"
+/// context-lines = 2
+/// diff = "two-line"
+/// write = false
+/// keymap = "vim"
+///
+/// [colors]
+/// glob = "blue"
+/// search = "#ebcb8b"
+/// muted = "244"
+///
+/// [keys]
+/// write = "f10"
+/// quit = ["esc", "ctrl+q"]
+/// ```
+#[derive(Debug, PartialEq, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Glob of the first search.
+    pub glob: Option<String>,
+    /// Patterns of the files never searched.
+    pub ignore: Vec<String>,
+    /// File types searched, named as in the `t:` modifier.
+    pub types: Vec<String>,
+    /// Syntax highlighting theme of the previews, one of syntect's, `Nord`
+    /// or a `.tmTheme` file of `~/.config/rplc/themes`.
+    pub theme: Option<String>,
+    /// Colours of the interface, by element of [`Palette::ELEMENTS`].
+    pub colors: BTreeMap<String, String>,
+    /// Unchanged lines shown around changes.
+    pub context_lines: Option<usize>,
+    /// How replacements are shown: `compact` or `two-line`.
+    pub diff: Option<String>,
+    /// Writes the changes of the classic mode without asking. Only read from
+    /// the user configuration.
+    pub write: Option<bool>,
+    pub eol_insensitive: Option<bool>,
+    pub max_filesize: Option<String>,
+    pub compact: Option<bool>,
+    /// Preset the key bindings start from: `default`, `vim` or `emacs`.
+    pub keymap: Option<String>,
+    /// Keys of the actions, replacing those of the preset.
+    pub keys: BTreeMap<String, Keys>,
+}
+
+/// One key or several for an action of the `[keys]` table.
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Keys {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Keys {
+    fn names(&self) -> &[String] {
+        match self {
+            Keys::One(name) => std::slice::from_ref(name),
+            Keys::Many(names) => names,
+        }
+    }
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/rplc/config.toml`, or `~/.config/rplc/config.toml`.
+    pub fn user_path() -> Option<PathBuf> {
+        let config = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(config) if !config.is_empty() => PathBuf::from(config),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(config.join("rplc").join("config.toml"))
+    }
+
+    /// Nearest `.rplc.toml` of `dir` or its parents.
+    pub fn project_path(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|dir| dir.join(PROJECT_CONFIG))
+            .find(|path| path.is_file())
+    }
+
+    /// Reads the user configuration then the project one, which overrides it.
+    /// Missing files are left out.
+    pub fn load() -> Result<Config, Error> {
+        let mut config = match Config::user_path() {
+            Some(path) if path.is_file() => Config::from_file(&path)?,
+            _ => Config::default(),
+        };
+        let project = std::env::current_dir()
+            .ok()
+            .and_then(|dir| Config::project_path(&dir));
+        if let Some(path) = project {
+            config = config.merge_project(Config::from_file(&path)?);
+        }
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, Error> {
+        let content = std::fs::read_to_string(path)?;
+        toml::from_str(&content).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e.message()),
+            )
+        })
+    }
+
+    /// Layers `other` over `self`: its values replace those of `self`, while
+    /// ignore rules, file types, colours and keys are added to them.
+    pub fn merge(mut self, other: Config) -> Config {
+        self.ignore.extend(other.ignore);
+        self.types.extend(other.types);
+        self.colors.extend(other.colors);
+        self.keys.extend(other.keys);
+        Config {
+            glob: other.glob.or(self.glob),
+            theme: other.theme.or(self.theme),
+            context_lines: other.context_lines.or(self.context_lines),
+            diff: other.diff.or(self.diff),
+            write: other.write.or(self.write),
+            eol_insensitive: other.eol_insensitive.or(self.eol_insensitive),
+            max_filesize: other.max_filesize.or(self.max_filesize),
+            compact: other.compact.or(self.compact),
+            keymap: other.keymap.or(self.keymap),
+            ..self
+        }
+    }
+
+    /// Layers the configuration of a project over `self` like
+    /// [`Config::merge`], but for `write`: a repository must not have its
+    /// changes written without asking.
+    pub fn merge_project(self, project: Config) -> Config {
+        self.merge(Config {
+            write: None,
+            ..project
+        })
+    }
+
+    /// Search options of the configuration, checking the ignore rules, file
+    /// types and size it gives.
+    pub fn search_options(&self) -> Result<SearchOptions, Error> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+        let mut options = SearchOptions {
+            eol_insensitive: self.eol_insensitive.unwrap_or(false),
+            context: self.context_lines.unwrap_or(0),
+            ..SearchOptions::default()
+        };
+        if let Some(diff) = &self.diff {
+            options.diff = diff.parse().map_err(invalid)?;
+        }
+        if let Some(size) = &self.max_filesize {
+            options.max_filesize = parse_size(size).map_err(invalid)?;
+        }
+        for name in &self.types {
+            let extensions = file_type_extensions(name)
+                .ok_or_else(|| invalid(format!("unknown file type {}", name)))?;
+            options.extensions.extend(extensions);
+        }
+        for pattern in &self.ignore {
+            let pattern = Pattern::new(pattern)
+                .map_err(|e| invalid(format!("invalid ignore pattern {}: {}", pattern, e.msg)))?;
+            options.exclude.push(pattern);
+        }
+        Ok(options)
+    }
+
+    /// Name of the syntax theme, checking that it exists.
+    pub fn theme(&self) -> Result<String, Error> {
+        let theme = self.theme.as_deref().unwrap_or(DEFAULT_THEME);
+        let names = theme_names();
+        match names.iter().any(|name| name == theme) {
+            true => Ok(theme.to_string()),
+            false => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "unknown theme {}, expected one of {}",
+                    theme,
+                    names.join(", ")
+                ),
+            )),
+        }
+    }
+
+    /// Colours of the interface with those of the `[colors]` table, as
+    /// close as a terminal of `depth` shows them.
+    pub fn palette(&self, depth: ColorDepth) -> Result<Palette, Error> {
+        let mut palette = Palette::default();
+        for (element, color) in &self.colors {
+            palette
+                .set(element, color)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        }
+        Ok(palette.convert(depth))
+    }
+
+    /// Key bindings of the preset, with the keys of the `[keys]` table.
+    pub fn keymap(&self) -> Result<Keymap, Error> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+        let preset = self.keymap.as_deref().unwrap_or("default");
+        let mut keymap = Keymap::preset(preset).ok_or_else(|| {
+            invalid(format!(
+                "unknown keymap {}, expected one of {}",
+                preset,
+                Keymap::PRESETS.join(", ")
+            ))
+        })?;
+        for (name, keys) in &self.keys {
+            let action = Action::from_name(name)
+                .ok_or_else(|| invalid(format!("unknown action {}", name)))?;
+            let keys = keys
+                .names()
+                .iter()
+                .map(|key| key.parse::<KeyBinding>())
+                .collect::<Result<Vec<KeyBinding>, String>>()
+                .map_err(|e| invalid(format!("{} for {}", e, name)))?;
+            keymap.rebind(action, &keys);
+        }
+        Ok(keymap)
+    }
+}
+
+#[test]
+fn layer_project_over_user_config() {
+    let user: Config = toml::from_str(
+        "glob = \"src/**/*\"\nignore = [\"target/**\"]\ncontext-lines = 2\n[colors]\nglob = \"blue\"",
+    )
+    .unwrap();
+    let project: Config = toml::from_str(
+        "glob = \"crates/**/*.rs\"\nignore = [\"*.lock\"]\ntypes = [\"rust\"]\ndiff = \"two-line\"",
+    )
+    .unwrap();
+    let config = user.merge(project);
+    assert_eq!(config.glob.as_deref(), Some("crates/**/*.rs"));
+    assert_eq!(config.ignore, vec!["target/**", "*.lock"]);
+    assert_eq!(config.context_lines, Some(2));
+    assert_eq!(config.colors["glob"], "blue");
+
+    let options = config.search_options().unwrap();
+    assert_eq!(options.context, 2);
+    assert_eq!(options.diff, DiffStyle::TwoLine);
+    assert_eq!(options.extensions, vec!["rs".to_string()]);
+    assert!(!options.includes("Cargo.lock"));
+    assert!(toml::from_str::<Config>("unknown = 1").is_err());
+}
+
+#[test]
+fn bind_configured_keys() {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    let user: Config =
+        toml::from_str("keymap = \"emacs\"\n[keys]\nwrite = \"f10\"\nquit = [\"esc\", \"ctrl+q\"]")
+            .unwrap();
+    let project: Config = toml::from_str("[keys]\nwrite = []").unwrap();
+    let keymap = user.clone().keymap().unwrap();
+    assert_eq!(keymap.label(Action::Write).as_deref(), Some("F10"));
+    assert_eq!(keymap.label(Action::HistoryNext).as_deref(), Some("ALT+N"));
+    let ctrl_q = KeyEvent::new(KeyCode::Char('q'), KeyModifiers::CONTROL);
+    assert_eq!(keymap.action(ctrl_q), Some(Action::Quit));
+    assert!(user
+        .merge(project)
+        .keymap()
+        .unwrap()
+        .keys(Action::Write)
+        .is_empty());
+
+    for invalid in [
+        "keymap = \"nano\"",
+        "[keys]\nexplode = \"f1\"",
+        "[keys]\nquit = \"hyper+q\"",
+    ] {
+        let config: Config = toml::from_str(invalid).unwrap();
+        assert!(config.keymap().is_err());
+    }
+}
+
+#[test]
+fn find_project_config() {
+    let dir = TempDir::new("find-project-config");
+    let nested = dir.join("a").join("b");
+    std::fs::create_dir_all(&nested).unwrap();
+    std::fs::write(dir.join(PROJECT_CONFIG), "types = [\"cobol\"]").unwrap();
+
+    let path = Config::project_path(&nested).unwrap();
+    assert_eq!(path, dir.join(PROJECT_CONFIG));
+    let config = Config::from_file(&path).unwrap();
+    assert!(config.search_options().is_err());
+    std::fs::write(&path, "context-lines = \"two\"").unwrap();
+    assert!(Config::from_file(&path).is_err());
+}
+
+#[test]
+fn select_theme_and_colors() {
+    use crossterm::style::Color;
+
+    let config: Config =
+        toml::from_str("theme = \"base16-ocean.dark\"\n[colors]\nglob = \"#88c0d0\"").unwrap();
+    assert_eq!(config.theme().unwrap(), "base16-ocean.dark");
+    assert_eq!(Config::default().theme().unwrap(), DEFAULT_THEME);
+    let palette = config.palette(ColorDepth::Ansi256).unwrap();
+    assert_eq!(palette.glob, Color::AnsiValue(110));
+    assert_eq!(palette.search, Palette::default().search);
+
+    for invalid in [
+        "theme = \"Solarized (neon)\"",
+        "[colors]\nprompt = \"blue\"",
+        "[colors]\nglob = \"#00\"",
+    ] {
+        let config: Config = toml::from_str(invalid).unwrap();
+        assert!(config.theme().is_err() || config.palette(ColorDepth::TrueColor).is_err());
+    }
+}
+
+#[test]
+fn ignore_write_of_project_config() {
+    let project: Config = toml::from_str("write = true").unwrap();
+    let config = Config::default().merge_project(project);
+    assert_eq!(config.write, None);
+    let user: Config = toml::from_str("write = true").unwrap();
+    let project: Config = toml::from_str("write = false").unwrap();
+    assert_eq!(user.merge_project(project).write, Some(true));
+}
@@ -1,43 +1,519 @@
+use std::borrow::Cow;
+use std::cmp::max;
+use std::ops::Range;
+use std::str::FromStr;
+
 use crossterm::style::{Color, Stylize};
 
+use super::matcher::Matcher;
+use super::renderer::strip_styles;
+#[cfg(test)]
+use super::syntax_highlight::DEFAULT_THEME;
+use super::syntax_highlight::{LineState, SyntaxHighlighter};
+
+/// How replacements are shown in the previews.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum DiffStyle {
+    /// Each changed line once, the removed text struck out before the text
+    /// added.
+    #[default]
+    Compact,
+    /// The changed lines as they are, then as they would be.
+    TwoLine,
+}
+
+impl FromStr for DiffStyle {
+    type Err = String;
+
+    fn from_str(style: &str) -> Result<DiffStyle, String> {
+        match style {
+            "compact" => Ok(DiffStyle::Compact),
+            "two-line" => Ok(DiffStyle::TwoLine),
+            _ => Err(format!(
+                "invalid diff style {}, expected compact or two-line",
+                style
+            )),
+        }
+    }
+}
+
+/// Lines rendered from a file, telling apart the ones showing changes from
+/// the unchanged ones shown as context around them.
+#[derive(Debug, PartialEq, Default, Clone)]
+pub struct Preview {
+    pub lines: Vec<String>,
+    /// Indexes in `lines` of the lines showing changes or matches, with the
+    /// indexes of those among the matches of the file. The `+` lines of
+    /// two-line diffs are left out, their changes being the ones of the `-`
+    /// lines above them.
+    pub matched: Vec<(usize, Range<usize>)>,
+}
+
+/// Text of changed lines with its syntax colours, and the ranges removed
+/// and added, or matched without a substitute.
+#[derive(Default)]
+struct Marked {
+    text: String,
+    colors: Vec<(Range<usize>, Color)>,
+    removed: Vec<Range<usize>>,
+    added: Vec<Range<usize>>,
+    /// Index among the matches of the file of the first marked range.
+    first: usize,
+}
+
+impl Marked {
+    /// Colours `text` from `state`, the parse state of its first line.
+    fn new(text: String, added: Vec<Range<usize>>, state: Option<&LineState>) -> Marked {
+        let colors = state.map(|state| state.colors(&text)).unwrap_or_default();
+        Marked {
+            text,
+            colors,
+            added,
+            ..Marked::default()
+        }
+    }
+
+    /// Appends `range` of `source`, coloured as it is there. Returns where
+    /// it was appended.
+    fn push(&mut self, source: &Marked, range: Range<usize>) -> Range<usize> {
+        let offset = self.text.len();
+        self.text.push_str(&source.text[range.clone()]);
+        for (colored, color) in &source.colors {
+            let (start, end) = (colored.start.max(range.start), colored.end.min(range.end));
+            if start < end {
+                let shifted = start - range.start + offset..end - range.start + offset;
+                self.colors.push((shifted, *color));
+            }
+        }
+        offset..self.text.len()
+    }
+
+    /// The text with `substitutes` in place of the removed ranges, added in
+    /// the text returned.
+    fn replaced(&self, substitutes: &[Cow<str>], state: Option<&LineState>) -> Marked {
+        let mut text = String::new();
+        let mut added = vec![];
+        let mut position = 0;
+        for (range, substitute) in self.removed.iter().zip(substitutes) {
+            text.push_str(&self.text[position..range.start]);
+            let mark = text.len();
+            text.push_str(substitute);
+            added.push(mark..text.len());
+            position = range.end;
+        }
+        text.push_str(&self.text[position..]);
+        Marked {
+            first: self.first,
+            ..Marked::new(text, added, state)
+        }
+    }
+
+    /// Renders the `line` range of the text with its colours, the removed
+    /// parts struck out and the added ones in bold: on backgrounds over
+    /// syntax colours, in red and green without them.
+    fn paint_line(&self, line: Range<usize>, syntax: Option<&SyntaxHighlighter>) -> String {
+        let mut bounds = vec![line.start, line.end];
+        let ranges = self.colors.iter().map(|(range, _)| range);
+        for range in ranges.chain(&self.removed).chain(&self.added) {
+            bounds.extend([range.start, range.end]);
+        }
+        bounds.retain(|bound| line.contains(bound) || *bound == line.end);
+        bounds.sort_unstable();
+        bounds.dedup();
+
+        let mut painted = String::new();
+        for part in bounds.windows(2) {
+            let (start, end) = (part[0], part[1]);
+            let removed = self.removed.iter().any(|range| range.contains(&start));
+            let added = self.added.iter().any(|range| range.contains(&start));
+            let color = self
+                .colors
+                .iter()
+                .find(|(range, _)| range.contains(&start))
+                .map(|(_, color)| *color);
+            let mut styled = self.text[start..end].stylize();
+            if let Some(color) = color {
+                styled = styled.with(color);
+            }
+            styled = match (removed, added, syntax) {
+                (true, _, Some(syntax)) => styled.on(syntax.removed_background()).crossed_out(),
+                (true, _, None) => styled.with(Color::Red).crossed_out(),
+                (_, true, Some(syntax)) => styled.on(syntax.mark_background()).bold(),
+                (_, true, None) => styled.with(Color::Green).bold(),
+                _ if color.is_some() => styled,
+                _ => {
+                    painted.push_str(&self.text[start..end]);
+                    continue;
+                }
+            };
+            painted.push_str(&styled.to_string());
+        }
+        painted
+    }
+
+    /// Indexes among the matches of the file of the removed or added ranges
+    /// the `line` range touches, empty ones included.
+    fn touched(&self, line: Range<usize>) -> Range<usize> {
+        let touches = |range: &Range<usize>| {
+            range.start <= line.end && max(range.end, range.start + 1) > line.start
+        };
+        let marked = max(self.removed.len(), self.added.len());
+        let mut touched = (0..marked).filter(|i| {
+            self.removed.get(*i).is_some_and(touches) || self.added.get(*i).is_some_and(touches)
+        });
+        match touched.next() {
+            Some(first) => {
+                let last = touched.next_back().unwrap_or(first);
+                self.first + first..self.first + last + 1
+            }
+            None => 0..0,
+        }
+    }
+
+    /// Renders the whole lines of `lines`, numbered from `number` and
+    /// prefixed by `marker`. Returns the number of lines rendered.
+    fn render(
+        &self,
+        lines: Range<usize>,
+        number: usize,
+        marker: &str,
+        syntax: Option<&SyntaxHighlighter>,
+        decorated: &mut Preview,
+    ) -> usize {
+        let mut start = lines.start;
+        let mut count = 0;
+        for line in self.text[lines].split('\n') {
+            let end = start + line.trim_end_matches('\r').len();
+            let touched = self.touched(start..end);
+            if !touched.is_empty() {
+                decorated.matched.push((decorated.lines.len(), touched));
+            }
+            decorated.lines.push(format!(
+                "{: >4} {}{}",
+                (number + count).to_string().stylize().with(Color::DarkGrey),
+                marker,
+                self.paint_line(start..end, syntax)
+            ));
+            start += line.len() + 1;
+            count += 1;
+        }
+        count
+    }
+}
+
+/// Lines of `before` touched by its removed ranges, lines next to each other
+/// in a single block. A removed line break joins the next line to the block.
+fn changed_blocks(before: &Marked) -> Vec<Range<usize>> {
+    let text = &before.text;
+    let mut blocks: Vec<Range<usize>> = vec![];
+    for range in &before.removed {
+        let block = line_start(text, range.start)..line_end(text, range.end.min(text.len()));
+        match blocks.last_mut() {
+            Some(previous) if block.start <= previous.end + 1 => {
+                previous.end = max(previous.end, block.end);
+            }
+            _ => blocks.push(block),
+        }
+    }
+    blocks
+}
+
+/// Offset in `after` of an offset of `before` outside the replacements.
+fn after_offset(before: &Marked, after: &Marked, offset: usize) -> usize {
+    let replaced = before.removed.iter().zip(&after.added);
+    replaced
+        .filter(|(removed, _)| removed.end <= offset)
+        .fold(offset, |offset, (removed, added)| {
+            offset + added.len() - removed.len()
+        })
+}
+
+/// Renders `before` then `after` for each block of changed lines, with the
+/// unchanged lines around them once, numbered from `number`.
+fn render_two_lines(
+    before: &Marked,
+    after: &Marked,
+    number: usize,
+    syntax: Option<&SyntaxHighlighter>,
+    decorated: &mut Preview,
+) {
+    let text = &before.text;
+    let (removed, added) = (
+        "- ".with(Color::Red).to_string(),
+        "+ ".with(Color::Green).to_string(),
+    );
+    let mut position = 0;
+    let mut number = number;
+    for block in changed_blocks(before) {
+        if block.start > position {
+            number += before.render(position..block.start - 1, number, "  ", syntax, decorated);
+        }
+        let lines = before.render(block.clone(), number, &removed, syntax, decorated);
+        let changed =
+            after_offset(before, after, block.start)..after_offset(before, after, block.end);
+        // The changes are unselected from the `-` lines only.
+        let matched = decorated.matched.len();
+        after.render(changed, number, &added, syntax, decorated);
+        decorated.matched.truncate(matched);
+        number += lines;
+        position = block.end + 1;
+    }
+    if position <= text.len() {
+        before.render(position..text.len(), number, "  ", syntax, decorated);
+    }
+}
+
+fn line_start(content: &str, offset: usize) -> usize {
+    memchr::memrchr(b'\n', &content.as_bytes()[..offset])
+        .map(|i| i + 1)
+        .unwrap_or(0)
+}
+
+fn line_end(content: &str, offset: usize) -> usize {
+    memchr::memchr(b'\n', &content.as_bytes()[offset..])
+        .map(|i| offset + i)
+        .unwrap_or(content.len())
+}
+
+/// Marks lines left out between distant changes.
+pub fn skipped_line() -> String {
+    "..."
+        .to_string()
+        .stylize()
+        .with(Color::DarkGrey)
+        .to_string()
+}
+
+/// Number of a line rendered by [`happend_changes_in_file`], `None` for
+/// skipped lines.
+pub fn preview_line_number(line: &str) -> Option<usize> {
+    strip_styles(line).split_whitespace().next()?.parse().ok()
+}
+
+/// End of the line `count` lines after the one ending at `offset`, stopping
+/// at the last line of `content`.
+fn lines_after(content: &str, offset: usize, count: usize) -> usize {
+    let mut end = offset;
+    for _ in 0..count {
+        if end + 1 >= content.len() {
+            break;
+        }
+        end = line_end(content, end + 1);
+    }
+    end
+}
+
+/// Start of the line `count` lines before the one starting at `offset`, not
+/// going back past `limit`.
+fn lines_before(content: &str, offset: usize, count: usize, limit: usize) -> usize {
+    let mut start = offset;
+    for _ in 0..count {
+        if start == 0 || start <= limit {
+            break;
+        }
+        start = line_start(content, start - 1);
+    }
+    max(start, limit)
+}
+
+/// Texts replacing `matches` of `content`, captures expanded.
+fn substitutes<'a>(
+    content: &str,
+    matches: &[Range<usize>],
+    matcher: &Matcher,
+    substitute: &'a str,
+) -> Vec<Cow<'a, str>> {
+    matches
+        .iter()
+        .map(|range| matcher.substitute(content, range, substitute))
+        .collect()
+}
+
+/// Renders the lines of `content` touched by `query` with `substitute`
+/// applied, or with the matches highlighted as they are without one, and
+/// `context` unchanged lines around them. Matching runs on the whole buffer
+/// so queries spanning several lines (or containing `\r\n`) are shown like
+/// they are written. Only the matched lines are scanned for boundaries, so
+/// large memory-mapped files are never split into a full list of lines.
 pub fn happend_changes_in_file(
-    lines: &[&str],
-    query: String,
-    substitute: String,
-) -> (Vec<String>, usize) {
-    let mut decorated = vec![];
-    let mut skipped = false;
-    let mut changes = 0;
-    for (i, line) in lines.iter().enumerate() {
-        if line.contains(&query) {
-            skipped = false;
-            let mut line = line.replace(
-                &query,
-                &format!("{}", substitute.clone().stylize().with(Color::Green).bold()),
-            );
+    content: &str,
+    matcher: &Matcher,
+    substitute: Option<&str>,
+    context: usize,
+    syntax: Option<&SyntaxHighlighter>,
+    diff: DiffStyle,
+) -> (Preview, usize) {
+    let matches = matcher.find_all(content);
 
-            changes += line.matches(&substitute.clone()).count();
-            line = format!(
-                "{: >4} {}",
-                (i + 1).to_string().stylize().with(Color::DarkGrey),
-                line
+    let mut decorated = Preview::default();
+    // Parsed from the start of the file so that groups starting inside a
+    // comment or a string are coloured as such.
+    let mut state = syntax.map(SyntaxHighlighter::state);
+    let mut next_start = 0;
+    let mut line_number = 0;
+    let mut counted = 0;
+    let mut i = 0;
+    while i < matches.len() {
+        let start = lines_before(
+            content,
+            line_start(content, matches[i].start),
+            context,
+            next_start,
+        );
+        let mut newline = line_end(content, matches[i].start);
+        let mut end = i;
+        while end < matches.len() {
+            let range = &matches[end];
+            // Changes whose context touches are shown together.
+            if range.start > lines_after(content, newline, 2 * context) {
+                break;
+            }
+            newline = max(
+                newline,
+                line_end(content, max(range.start, range.end.saturating_sub(1))),
             );
+            end += 1;
+        }
 
-            decorated.push(line);
-        } else {
-            if !skipped {
-                decorated.push(
-                    "..."
-                        .to_string()
-                        .stylize()
-                        .with(Color::DarkGrey)
-                        .to_string(),
-                );
-                skipped = true;
+        if start > next_start {
+            decorated.lines.push(skipped_line());
+        }
+        line_number += memchr::memchr_iter(b'\n', &content.as_bytes()[counted..start]).count();
+        counted = start;
+
+        let mut group_end = max(newline, matches[end - 1].end);
+        if group_end == newline {
+            group_end = lines_after(content, newline, context);
+            newline = group_end;
+        }
+        let relative: Vec<Range<usize>> = matches[i..end]
+            .iter()
+            .map(|range| range.start - start..range.end - start)
+            .collect();
+        let group = content[start..group_end].to_string();
+        let number = line_number + 1;
+        if let Some(state) = &mut state {
+            state.advance(content, start);
+        }
+        match substitute {
+            None => {
+                let found = Marked {
+                    first: i,
+                    ..Marked::new(group, relative, state.as_ref())
+                };
+                found.render(0..found.text.len(), number, "", syntax, &mut decorated);
+            }
+            Some(substitute) => {
+                let substitutes = substitutes(content, &matches[i..end], matcher, substitute);
+                let mut before = Marked {
+                    first: i,
+                    ..Marked::new(group, vec![], state.as_ref())
+                };
+                before.removed = relative;
+                let after = before.replaced(&substitutes, state.as_ref());
+
+                match diff {
+                    DiffStyle::Compact => {
+                        let mut inline = Marked {
+                            first: i,
+                            ..Marked::default()
+                        };
+                        let mut position = 0;
+                        for (removed, added) in before.removed.iter().zip(&after.added) {
+                            inline.push(&before, position..removed.start);
+                            let struck = inline.push(&before, removed.clone());
+                            inline.removed.push(struck);
+                            let inserted = inline.push(&after, added.clone());
+                            inline.added.push(inserted);
+                            position = removed.end;
+                        }
+                        inline.push(&before, position..before.text.len());
+                        let lines = 0..inline.text.len();
+                        inline.render(lines, number, "", syntax, &mut decorated);
+                    }
+                    DiffStyle::TwoLine => {
+                        render_two_lines(&before, &after, number, syntax, &mut decorated)
+                    }
+                }
             }
         }
+        next_start = if group_end > newline {
+            group_end
+        } else {
+            newline + 1
+        };
+        i = end;
+    }
+    if next_start < content.len() {
+        decorated.lines.push(skipped_line());
+    }
+    (decorated, matches.len())
+}
+
+/// Renders the whole of `content` in rows of the line as it is and the line
+/// with `substitute` applied, each side numbered on its own. Changed lines
+/// face each other, with blank rows where one side has more of them.
+/// Without a substitute the matches are highlighted on the left and the
+/// right is left blank.
+pub fn side_by_side(
+    content: &str,
+    matcher: &Matcher,
+    substitute: Option<&str>,
+    syntax: Option<&SyntaxHighlighter>,
+) -> Vec<(String, String)> {
+    let matches = matcher.find_all(content);
+    let state = syntax.map(SyntaxHighlighter::state);
+    // A final line break does not start another line.
+    let end = content.strip_suffix('\n').unwrap_or(content).len();
+    let substitute = match substitute {
+        Some(substitute) => substitute,
+        None => {
+            let mut lines = Preview::default();
+            Marked::new(content.to_string(), matches, state.as_ref()).render(
+                0..end,
+                1,
+                "",
+                syntax,
+                &mut lines,
+            );
+            return lines
+                .lines
+                .into_iter()
+                .map(|line| (line, String::new()))
+                .collect();
+        }
+    };
+    let substitutes = substitutes(content, &matches, matcher, substitute);
+    let mut before = Marked::new(content.to_string(), vec![], state.as_ref());
+    before.removed = matches;
+    let after = before.replaced(&substitutes, state.as_ref());
+
+    let mut rows = vec![];
+    let mut numbers = (1, 1);
+    let mut face = |lines: Range<usize>| {
+        let (mut left, mut right) = (Preview::default(), Preview::default());
+        let changed =
+            after_offset(&before, &after, lines.start)..after_offset(&before, &after, lines.end);
+        numbers.0 += before.render(lines, numbers.0, "", syntax, &mut left);
+        numbers.1 += after.render(changed, numbers.1, "", syntax, &mut right);
+        let (mut left, mut right) = (left.lines, right.lines);
+        let height = max(left.len(), right.len());
+        left.resize(height, String::new());
+        right.resize(height, String::new());
+        rows.extend(left.into_iter().zip(right));
+    };
+    let mut position = 0;
+    for block in changed_blocks(&before) {
+        if block.start > position {
+            face(position..block.start - 1);
+        }
+        face(block.clone());
+        position = block.end + 1;
+    }
+    if position <= end {
+        face(position..end);
     }
-    (decorated, changes)
+    rows
 }
 
 pub fn decorate_file_content(
@@ -89,18 +565,316 @@ fn handle_non_empty() {
 
 #[test]
 fn handle_changes() {
-    let lines = vec!["line 1", "line 2", "line 3"];
-    let query = "line".to_string();
-    let substitute = "test".to_string();
+    let content = "line 1\nline 2\nline 3";
+    let matcher = Matcher::new("line", false);
+    let (preview, changes) =
+        happend_changes_in_file(content, &matcher, Some("test"), 0, None, DiffStyle::Compact);
     assert_eq!(
-        happend_changes_in_file(&lines, query, substitute),
+        (preview.lines, changes),
         (
             vec![
-                "\u{1b}[38;5;8m1\u{1b}[39m \u{1b}[38;5;10m\u{1b}[1mtest\u{1b}[0m 1".to_string(),
-                "\u{1b}[38;5;8m2\u{1b}[39m \u{1b}[38;5;10m\u{1b}[1mtest\u{1b}[0m 2".to_string(),
-                "\u{1b}[38;5;8m3\u{1b}[39m \u{1b}[38;5;10m\u{1b}[1mtest\u{1b}[0m 3".to_string(),
+                "\u{1b}[38;5;8m1\u{1b}[39m \u{1b}[38;5;9m\u{1b}[9mline\u{1b}[0m\u{1b}[38;5;10m\u{1b}[1mtest\u{1b}[0m 1".to_string(),
+                "\u{1b}[38;5;8m2\u{1b}[39m \u{1b}[38;5;9m\u{1b}[9mline\u{1b}[0m\u{1b}[38;5;10m\u{1b}[1mtest\u{1b}[0m 2".to_string(),
+                "\u{1b}[38;5;8m3\u{1b}[39m \u{1b}[38;5;9m\u{1b}[9mline\u{1b}[0m\u{1b}[38;5;10m\u{1b}[1mtest\u{1b}[0m 3".to_string(),
             ],
             3
         )
     );
 }
+
+#[test]
+fn handle_crlf_changes() {
+    let content = "a\r\nb\r\nc\r\nd\r\n";
+    let (Preview { lines, .. }, changes) = happend_changes_in_file(
+        content,
+        &Matcher::new("b\r\nc", false),
+        Some("x"),
+        0,
+        None,
+        DiffStyle::Compact,
+    );
+    assert_eq!(changes, 1);
+    assert_eq!(
+        lines,
+        vec![
+            "..."
+                .to_string()
+                .stylize()
+                .with(Color::DarkGrey)
+                .to_string(),
+            format!(
+                "{} {}",
+                "2".to_string().stylize().with(Color::DarkGrey),
+                "b".stylize().with(Color::Red).crossed_out()
+            ),
+            format!(
+                "{} {}{}",
+                "3".to_string().stylize().with(Color::DarkGrey),
+                "c".stylize().with(Color::Red).crossed_out(),
+                "x".stylize().with(Color::Green).bold()
+            ),
+            "..."
+                .to_string()
+                .stylize()
+                .with(Color::DarkGrey)
+                .to_string(),
+        ]
+    );
+}
+
+#[test]
+fn handle_line_numbers_after_gaps() {
+    let content = "a\nb\nc\nd\na";
+    let (Preview { lines, .. }, changes) = happend_changes_in_file(
+        content,
+        &Matcher::new("a", false),
+        Some("x"),
+        0,
+        None,
+        DiffStyle::Compact,
+    );
+    let numbered = |n: &str| {
+        format!(
+            "{} {}{}",
+            n.to_string().stylize().with(Color::DarkGrey),
+            "a".stylize().with(Color::Red).crossed_out(),
+            "x".stylize().with(Color::Green).bold()
+        )
+    };
+    assert_eq!(changes, 2);
+    assert_eq!(lines, vec![numbered("1"), skipped_line(), numbered("5")]);
+}
+
+/// Compares the matcher and `happend_changes_in_file` against the previous
+/// per-line `contains`/`replace`/`matches` passes on a generated fixture.
+/// Run with `cargo test --release bench_ -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn bench_against_line_by_line() {
+    use std::time::{Duration, Instant};
+
+    fn legacy_changes_in_file(
+        lines: &[&str],
+        query: &str,
+        substitute: &str,
+    ) -> (Vec<String>, usize) {
+        let mut decorated = vec![];
+        let mut changes = 0;
+        for (i, line) in lines.iter().enumerate() {
+            if line.contains(query) {
+                let line = line.replace(
+                    query,
+                    &format!("{}", substitute.stylize().with(Color::Green).bold()),
+                );
+                changes += line.matches(substitute).count();
+                decorated.push(format!(
+                    "{: >4} {}",
+                    (i + 1).to_string().stylize().with(Color::DarkGrey),
+                    line
+                ));
+            }
+        }
+        (decorated, changes)
+    }
+
+    fn timed<T>(run: impl Fn() -> T) -> (T, Duration) {
+        let started = Instant::now();
+        let mut result = run();
+        for _ in 0..9 {
+            result = run();
+        }
+        (result, started.elapsed() / 10)
+    }
+
+    let mut content = String::new();
+    for i in 0..200_000 {
+        match i % 50 {
+            0 => content.push_str("    let pin = needle.replace(\"needle\", \"pin\");\n"),
+            _ => content.push_str("    an ordinary line of source code without any match\n"),
+        }
+    }
+    let matcher = Matcher::new("needle", false);
+
+    let ((_, legacy_changes), legacy) = timed(|| {
+        let lines = content.lines().collect::<Vec<&str>>();
+        legacy_changes_in_file(&lines, "needle", "thread")
+    });
+    let (found, matching) = timed(|| matcher.find_all(&content).len());
+    let ((_, changes), rendering) = timed(|| {
+        happend_changes_in_file(
+            &content,
+            &matcher,
+            Some("thread"),
+            0,
+            None,
+            DiffStyle::Compact,
+        )
+    });
+
+    let speedup = |d: Duration| legacy.as_secs_f64() / d.as_secs_f64().max(1e-9);
+    println!(
+        "{} bytes: line by line {:?}, matcher {:?} ({:.1}x), matcher + rendering {:?} ({:.1}x)",
+        content.len(),
+        legacy,
+        matching,
+        speedup(matching),
+        rendering,
+        speedup(rendering)
+    );
+    assert_eq!((legacy_changes, found, changes), (8_000, 8_000, 8_000));
+}
+
+#[test]
+fn handle_context_lines() {
+    let content = "1\n2\na\n4\n5\n6\n7\na\n9\n10\n11\n12\na\n";
+    let numbers = |lines: Vec<String>| {
+        lines
+            .iter()
+            .map(|line| strip_styles(line).trim().to_string())
+            .collect::<Vec<String>>()
+    };
+    let (Preview { lines, .. }, _) = happend_changes_in_file(
+        content,
+        &Matcher::new("a", false),
+        Some("x"),
+        2,
+        None,
+        DiffStyle::Compact,
+    );
+    assert_eq!(
+        numbers(lines),
+        vec![
+            "1 1", "2 2", "3 ax", "4 4", "5 5", "6 6", "7 7", "8 ax", "9 9", "10 10", "11 11",
+            "12 12", "13 ax"
+        ]
+    );
+    let (Preview { lines, .. }, _) = happend_changes_in_file(
+        content,
+        &Matcher::new("a", false),
+        Some("x"),
+        1,
+        None,
+        DiffStyle::Compact,
+    );
+    assert_eq!(preview_line_number(&lines[0]), None);
+    assert_eq!(preview_line_number(&lines[2]), Some(3));
+    assert_eq!(numbers(lines)[..4], ["...", "2 2", "3 ax", "4 4"]);
+}
+
+#[test]
+fn highlight_syntax_under_changes() {
+    let content = "fn main() {\n    let a = 1;\n}\n";
+    let syntax = SyntaxHighlighter::for_file("main.rs", content, DEFAULT_THEME).unwrap();
+    let matcher = Matcher::new("a", false);
+    let (Preview { lines, .. }, changes) = happend_changes_in_file(
+        content,
+        &matcher,
+        Some("b"),
+        0,
+        Some(&syntax),
+        DiffStyle::Compact,
+    );
+    assert_eq!(changes, 2);
+    assert_eq!(strip_styles(&lines[0]).trim(), "1 fn mabin() {");
+    assert_eq!(strip_styles(&lines[1]).trim(), "2     let ab = 1;");
+    assert!(lines[1].contains("\u{1b}[38;2;"));
+    assert_eq!(lines[1].matches("\u{1b}[48;2;67;76;94m").count(), 1);
+    assert_eq!(lines[1].matches("\u{1b}[48;2;76;43;49m").count(), 1);
+    assert!(!lines[1].contains("\u{1b}[38;5;10m"));
+}
+
+#[test]
+fn handle_two_line_changes() {
+    let plain = |lines: Vec<String>| {
+        lines
+            .iter()
+            .map(|line| strip_styles(line).trim().to_string())
+            .collect::<Vec<String>>()
+    };
+    let matcher = Matcher::new("a", false);
+    let content = "x a\nb\na a\nc\nd\ne";
+    let (Preview { lines, matched }, changes) =
+        happend_changes_in_file(content, &matcher, Some("z"), 1, None, DiffStyle::TwoLine);
+    assert_eq!(changes, 3);
+    assert_eq!(matched, vec![(0, 0..1), (3, 1..3)]);
+    assert_eq!(
+        plain(lines.clone()),
+        vec!["1 - x a", "1 + x z", "2   b", "3 - a a", "3 + z z", "4   c", "..."]
+    );
+    assert_eq!(preview_line_number(&lines[4]), Some(3));
+    assert!(lines[0].contains(&"a".with(Color::Red).crossed_out().to_string()));
+    assert!(lines[1].contains(&"z".with(Color::Green).bold().to_string()));
+
+    let content = "a\r\nb\r\nc\r\nd\r\n";
+    let matcher = Matcher::new("b\r\nc", false);
+    let (Preview { lines, matched }, _) =
+        happend_changes_in_file(content, &matcher, Some("x"), 0, None, DiffStyle::TwoLine);
+    assert_eq!(plain(lines), vec!["...", "2 - b", "3 - c", "2 + x", "..."]);
+    assert_eq!(matched, vec![(1, 0..1), (2, 0..1)]);
+    assert!("two-line".parse::<DiffStyle>().is_ok());
+    assert!("side-by-side".parse::<DiffStyle>().is_err());
+}
+
+#[test]
+fn handle_side_by_side() {
+    let plain = |rows: Vec<(String, String)>| {
+        rows.iter()
+            .map(|(left, right)| (strip_styles(left), strip_styles(right)))
+            .collect::<Vec<(String, String)>>()
+    };
+    let row = |left: &str, right: &str| (left.to_string(), right.to_string());
+    let matcher = Matcher::new("b", false);
+    let content = "a\nb\nc b\nd\n";
+    assert_eq!(
+        plain(side_by_side(content, &matcher, Some("x\ny"), None)),
+        vec![
+            row("1 a", "1 a"),
+            row("2 b", "2 x"),
+            row("3 c b", "3 y"),
+            row("", "4 c x"),
+            row("", "5 y"),
+            row("4 d", "6 d"),
+        ]
+    );
+    let rows = side_by_side(content, &matcher, None, None);
+    assert_eq!(rows.len(), 4);
+    assert_eq!(plain(rows.clone())[2], row("3 c b", ""));
+    assert!(rows[2]
+        .0
+        .contains(&"b".with(Color::Green).bold().to_string()));
+
+    let matcher = Matcher::new("b\r\n", false);
+    let rows = side_by_side("a\r\nb\r\nc", &matcher, Some(""), None);
+    assert_eq!(
+        plain(rows),
+        vec![row("1 a", "1 a"), row("2 b", "2 c"), row("3 c", "")]
+    );
+}
+
+#[test]
+fn highlight_syntax_from_file_start() {
+    let content = "/* a\nlet x = 1;\n*/\nfn x() {}\n";
+    let syntax = SyntaxHighlighter::for_file("main.rs", content, DEFAULT_THEME).unwrap();
+    let foreground = |text: &str| {
+        let colors = syntax.colors(content);
+        let start = content.find(text).unwrap();
+        match colors.iter().find(|(range, _)| range.contains(&start)) {
+            Some((_, Color::Rgb { r, g, b })) => format!("\u{1b}[38;2;{};{};{}m", r, g, b),
+            _ => panic!("{} is not coloured", text),
+        }
+    };
+    let (comment, keyword) = (foreground("let"), foreground("fn"));
+    assert_ne!(comment, keyword);
+    let (Preview { lines, .. }, _) = happend_changes_in_file(
+        content,
+        &Matcher::new("x", false),
+        Some("b"),
+        0,
+        Some(&syntax),
+        DiffStyle::Compact,
+    );
+    assert_eq!(strip_styles(&lines[1]).trim(), "2 let xb = 1;");
+    assert!(lines[1].contains(&comment));
+    assert!(!lines[1].contains(&keyword));
+    assert!(lines[3].contains(&keyword));
+}
@@ -0,0 +1,317 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use memmap2::Mmap;
+
+/// Files above this size are memory-mapped on demand instead of cached.
+const MMAP_THRESHOLD: u64 = 4 * 1024 * 1024;
+/// Upper bound, in bytes, of the file contents kept in memory.
+const CACHE_CAPACITY: usize = 64 * 1024 * 1024;
+/// How long the files listed for a glob are reused before listing it again.
+const GLOB_CACHE_TTL: Duration = Duration::from_secs(30);
+
+type GlobListing = (Instant, Vec<(String, FileMeta)>);
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FileMeta {
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl FileMeta {
+    pub fn from_path(path: &str) -> Result<FileMeta, Error> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(FileMeta {
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+pub enum FileContent {
+    Cached(Arc<String>),
+    Mapped(Mmap),
+}
+
+impl FileContent {
+    pub fn as_str(&self) -> Result<&str, Error> {
+        match self {
+            FileContent::Cached(content) => Ok(content.as_str()),
+            FileContent::Mapped(map) => {
+                std::str::from_utf8(map).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+            }
+        }
+    }
+}
+
+/// Least recently used cache of file contents, bounded by total size.
+/// Entries are only returned while the file modification time is unchanged.
+struct ContentCache {
+    entries: HashMap<String, (Arc<String>, u64, Option<SystemTime>)>,
+    recent: BTreeMap<u64, String>,
+    tick: u64,
+    size: usize,
+    capacity: usize,
+}
+
+impl ContentCache {
+    fn new(capacity: usize) -> ContentCache {
+        ContentCache {
+            entries: HashMap::new(),
+            recent: BTreeMap::new(),
+            tick: 0,
+            size: 0,
+            capacity,
+        }
+    }
+
+    fn get(&mut self, path: &str, modified: Option<SystemTime>) -> Option<Arc<String>> {
+        self.tick += 1;
+        let (content, tick, cached_modified) = self.entries.get_mut(path)?;
+        if modified.is_none() || *cached_modified != modified {
+            self.remove(path);
+            return None;
+        }
+        self.recent.remove(tick);
+        *tick = self.tick;
+        self.recent.insert(self.tick, path.to_string());
+        Some(content.clone())
+    }
+
+    fn insert(&mut self, path: String, content: Arc<String>, modified: Option<SystemTime>) {
+        self.remove(&path);
+        if content.len() > self.capacity {
+            return;
+        }
+        while self.size + content.len() > self.capacity {
+            let oldest = match self.recent.keys().next() {
+                Some(tick) => self.recent[tick].clone(),
+                None => break,
+            };
+            self.remove(&oldest);
+        }
+        self.tick += 1;
+        self.size += content.len();
+        self.recent.insert(self.tick, path.clone());
+        self.entries.insert(path, (content, self.tick, modified));
+    }
+
+    fn remove(&mut self, path: &str) {
+        if let Some((content, tick, _)) = self.entries.remove(path) {
+            self.recent.remove(&tick);
+            self.size -= content.len();
+        }
+    }
+}
+
+/// Lists the files matching `glob_pattern` with their metadata.
+pub fn list_files(glob_pattern: &str) -> Result<Vec<(String, FileMeta)>, Error> {
+    let paths = glob::glob(glob_pattern).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let mut files = vec![];
+    for path in paths.filter_map(|x| x.ok()) {
+        if !path.is_file() {
+            continue;
+        }
+        let file_name = path.to_str().unwrap_or("Could not read file name");
+        if let Ok(meta) = FileMeta::from_path(file_name) {
+            files.push((file_name.to_string(), meta));
+        }
+    }
+    Ok(files)
+}
+
+/// Shared handle on the content cache, cheap to clone into search workers.
+#[derive(Clone)]
+pub struct Contents {
+    cache: Arc<Mutex<ContentCache>>,
+}
+
+impl Default for Contents {
+    fn default() -> Contents {
+        Contents {
+            cache: Arc::new(Mutex::new(ContentCache::new(CACHE_CAPACITY))),
+        }
+    }
+}
+
+impl Contents {
+    /// Loads the content of `path`, from the cache when the file has not been
+    /// modified since it was read. Large files are memory-mapped and never
+    /// kept in the cache. Blocks while reading.
+    pub fn get_file(&self, path: &str) -> Result<FileContent, Error> {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let modified = metadata.modified().ok();
+        if let Some(content) = self.lock().get(path, modified) {
+            return Ok(FileContent::Cached(content));
+        }
+
+        if metadata.len() > MMAP_THRESHOLD {
+            // SAFETY: the map is read-only; a file truncated by another process
+            // while mapped is an accepted risk, as for any mmap-based search tool.
+            let map = unsafe { Mmap::map(&file)? };
+            return Ok(FileContent::Mapped(map));
+        }
+
+        let content = Arc::new(std::fs::read_to_string(path)?);
+        self.lock()
+            .insert(path.to_string(), content.clone(), modified);
+        Ok(FileContent::Cached(content))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ContentCache> {
+        // The cache stays consistent if a worker panicked while holding it.
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Files of the current glob, with the listings of recent globs and the
+/// content cache shared by searches.
+#[derive(Default)]
+pub struct FileStore {
+    files: BTreeMap<String, FileMeta>,
+    skipped: usize,
+    globs: HashMap<String, GlobListing>,
+    contents: Contents,
+}
+
+impl FileStore {
+    /// Replaces the listed files in one step, `skipped` being the number of
+    /// files left out for their size or for not being text.
+    pub fn replace_files(&mut self, files: Vec<(String, FileMeta)>, skipped: usize) {
+        self.files = files.into_iter().collect();
+        self.skipped = skipped;
+    }
+
+    pub fn files_names(&self) -> Vec<String> {
+        self.files.keys().cloned().collect()
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Files previously listed for `glob`, unless the listing is too old.
+    pub fn cached_glob(&self, glob: &str) -> Option<Vec<(String, FileMeta)>> {
+        match self.globs.get(glob) {
+            Some((listed, files)) if listed.elapsed() < GLOB_CACHE_TTL => Some(files.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn cache_glob(&mut self, glob: String, files: Vec<(String, FileMeta)>) {
+        self.globs
+            .retain(|_, (listed, _)| listed.elapsed() < GLOB_CACHE_TTL);
+        self.globs.insert(glob, (Instant::now(), files));
+    }
+
+    pub fn contents(&self) -> Contents {
+        self.contents.clone()
+    }
+}
+
+/// Parses sizes such as `512`, `100K`, `10M` or `1G`.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let (digits, multiplier) = match size.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&size[..size.len() - 1], 1024),
+        Some('M') => (&size[..size.len() - 1], 1024 * 1024),
+        Some('G') => (&size[..size.len() - 1], 1024 * 1024 * 1024),
+        _ => (size, 1),
+    };
+    let n = digits
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| format!("invalid size: {}", size))?;
+    n.checked_mul(multiplier)
+        .ok_or_else(|| format!("size too large: {}", size))
+}
+
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1024 * 1024 * 1024 => format!("{:.1} GiB", b as f64 / (1024.0 * 1024.0 * 1024.0)),
+        b if b >= 1024 * 1024 => format!("{:.1} MiB", b as f64 / (1024.0 * 1024.0)),
+        b if b >= 1024 => format!("{:.1} KiB", b as f64 / 1024.0),
+        b => format!("{} B", b),
+    }
+}
+
+#[test]
+fn cache_evicts_least_recently_used() {
+    let now = Some(SystemTime::now());
+    let mut cache = ContentCache::new(10);
+    cache.insert("a".to_string(), Arc::new("aaaa".to_string()), now);
+    cache.insert("b".to_string(), Arc::new("bbbb".to_string()), now);
+    assert!(cache.get("a", now).is_some());
+    cache.insert("c".to_string(), Arc::new("cccc".to_string()), now);
+    assert!(cache.get("a", now).is_some());
+    assert!(cache.get("b", now).is_none());
+    assert!(cache.get("c", now).is_some());
+    assert_eq!(cache.size, 8);
+
+    cache.insert("d".to_string(), Arc::new("d".repeat(11)), now);
+    assert!(cache.get("d", now).is_none());
+    assert_eq!(cache.size, 8);
+}
+
+#[test]
+fn cache_invalidates_modified_files() {
+    let before = Some(SystemTime::UNIX_EPOCH);
+    let after = Some(SystemTime::now());
+    let mut cache = ContentCache::new(10);
+    cache.insert("a".to_string(), Arc::new("aaaa".to_string()), before);
+    assert!(cache.get("a", before).is_some());
+    assert!(cache.get("a", after).is_none());
+    assert!(cache.get("a", before).is_none());
+    assert_eq!(cache.size, 0);
+}
+
+#[test]
+fn parse_human_sizes() {
+    assert_eq!(parse_size("512"), Ok(512));
+    assert_eq!(parse_size("2k"), Ok(2048));
+    assert_eq!(parse_size("10M"), Ok(10 * 1024 * 1024));
+    assert!(parse_size("ten").is_err());
+}
+
+#[test]
+fn reject_overflowing_sizes() {
+    assert_eq!(parse_size("16777215G"), Ok(16777215 << 30));
+    assert_eq!(
+        parse_size("17179869184G"),
+        Err("size too large: 17179869184G".to_string())
+    );
+    assert!(parse_size("18446744073709551616").is_err());
+}
+
+#[test]
+fn format_human_sizes() {
+    assert_eq!(format_size(12), "12 B");
+    assert_eq!(format_size(1536), "1.5 KiB");
+    assert_eq!(format_size(10 * 1024 * 1024), "10.0 MiB");
+}
+
+#[test]
+fn store_replaces_listing() {
+    let meta = FileMeta {
+        size: 1,
+        modified: None,
+    };
+    let mut store = FileStore::default();
+    store.cache_glob("*".to_string(), vec![("b".to_string(), meta.clone())]);
+    assert_eq!(
+        store.cached_glob("*"),
+        Some(vec![("b".to_string(), meta.clone())])
+    );
+    assert_eq!(store.cached_glob("src/*"), None);
+
+    store.replace_files(
+        vec![("b".to_string(), meta.clone()), ("a".to_string(), meta)],
+        2,
+    );
+    assert_eq!(store.files_names(), vec!["a".to_string(), "b".to_string()]);
+    assert_eq!(store.skipped(), 2);
+}
@@ -0,0 +1,262 @@
+use std::fs::{create_dir_all, OpenOptions};
+use std::io::{Error, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+
+use super::line_editor::LineEditor;
+#[cfg(test)]
+use super::temp_dir::TempDir;
+
+/// Entries kept, the oldest ones being dropped on load and once the file
+/// holds twice as many lines.
+const MAX_ENTRIES: usize = 1000;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct HistoryEntry {
+    pub at: DateTime<Local>,
+    /// Query as typed on the compact prompt line.
+    pub query: String,
+    /// Whether the changes were written to the files.
+    pub written: bool,
+}
+
+impl HistoryEntry {
+    /// Line of the history file: time, `w` for written queries, and the
+    /// query with tabs, line breaks and backslashes escaped.
+    fn to_line(&self) -> String {
+        let query = self
+            .query
+            .replace('\\', "\\\\")
+            .replace('\t', "\\t")
+            .replace('\n', "\\n");
+        let written = if self.written { "w" } else { "-" };
+        format!("{}\t{}\t{}", self.at.to_rfc3339(), written, query)
+    }
+
+    fn from_line(line: &str) -> Option<HistoryEntry> {
+        let mut parts = line.splitn(3, '\t');
+        let at = DateTime::parse_from_rfc3339(parts.next()?).ok()?;
+        let written = parts.next()? == "w";
+        let mut query = String::new();
+        let mut chars = parts.next()?.chars().peekable();
+        while let Some(char) = chars.next() {
+            let escaped = match char {
+                '\\' => chars.next_if(|next| ['\\', 't', 'n'].contains(next)),
+                _ => None,
+            };
+            query.push(match escaped {
+                Some('t') => '\t',
+                Some('n') => '\n',
+                Some(escaped) => escaped,
+                None => char,
+            });
+        }
+        Some(HistoryEntry {
+            at: at.with_timezone(&Local),
+            query,
+            written,
+        })
+    }
+}
+
+/// Queries of past sessions, oldest first, appended to a file as they are
+/// run. Without a file, the history only lasts for the session.
+#[derive(Debug, Default)]
+pub struct History {
+    path: Option<PathBuf>,
+    entries: Vec<HistoryEntry>,
+    /// Entry shown by CTRL+P and CTRL+N.
+    recalled: Option<usize>,
+    /// Query typed before recalling entries, restored past the newest one.
+    draft: String,
+    /// Lines of the file, rewritten with the entries kept past twice
+    /// `MAX_ENTRIES`.
+    lines: usize,
+}
+
+impl History {
+    /// `$XDG_STATE_HOME/rplc/history`, or `~/.local/state/rplc/history`.
+    pub fn default_path() -> Option<PathBuf> {
+        let state = match std::env::var_os("XDG_STATE_HOME") {
+            Some(state) if !state.is_empty() => PathBuf::from(state),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+        };
+        Some(state.join("rplc").join("history"))
+    }
+
+    /// Reads the history at `path`, which is created on the first query.
+    /// Unreadable lines are skipped.
+    pub fn load(path: &Path) -> Result<History, Error> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut entries = content
+            .lines()
+            .filter_map(HistoryEntry::from_line)
+            .collect::<Vec<HistoryEntry>>();
+        entries.drain(..entries.len().saturating_sub(MAX_ENTRIES));
+        Ok(History {
+            path: Some(path.to_path_buf()),
+            entries,
+            lines: content.lines().count(),
+            ..History::default()
+        })
+    }
+
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    /// Records `query`, unless it repeats the latest entry: the same query,
+    /// written then or not written now.
+    pub fn push(&mut self, query: &str, written: bool) -> Result<(), Error> {
+        self.recalled = None;
+        let repeated = self
+            .entries
+            .last()
+            .is_some_and(|last| last.query == query && (last.written || !written));
+        if query.trim().is_empty() || repeated {
+            return Ok(());
+        }
+        let entry = HistoryEntry {
+            at: Local::now(),
+            query: query.to_string(),
+            written,
+        };
+        self.entries.push(entry);
+        self.entries
+            .drain(..self.entries.len().saturating_sub(MAX_ENTRIES));
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        if self.lines < 2 * MAX_ENTRIES {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{}", self.entries[self.entries.len() - 1].to_line())?;
+            self.lines += 1;
+            return Ok(());
+        }
+        // Written aside then renamed, so that the file is never left half
+        // written.
+        let rotated = path.with_extension("tmp");
+        let lines = self
+            .entries
+            .iter()
+            .map(|entry| entry.to_line() + "\n")
+            .collect::<String>();
+        std::fs::write(&rotated, lines)?;
+        std::fs::rename(&rotated, path)?;
+        self.lines = self.entries.len();
+        Ok(())
+    }
+
+    /// Query before the one recalled, starting from the newest. `current` is
+    /// kept to be restored by `recall_next`.
+    pub fn recall_previous(&mut self, current: &str) -> Option<&str> {
+        let index = match self.recalled {
+            Some(0) => return None,
+            Some(index) => index - 1,
+            None => {
+                self.draft = current.to_string();
+                self.entries.len().checked_sub(1)?
+            }
+        };
+        self.recalled = Some(index);
+        Some(&self.entries[index].query)
+    }
+
+    /// Query after the one recalled, or the query typed before recalling.
+    pub fn recall_next(&mut self) -> Option<&str> {
+        let index = self.recalled? + 1;
+        if index == self.entries.len() {
+            self.recalled = None;
+            return Some(&self.draft);
+        }
+        self.recalled = Some(index);
+        Some(&self.entries[index].query)
+    }
+
+    /// Index of the newest entry before `before` containing `term`.
+    pub fn search(&self, term: &str, before: usize) -> Option<usize> {
+        self.entries[..before.min(self.entries.len())]
+            .iter()
+            .rposition(|entry| entry.query.contains(term))
+    }
+}
+
+/// Reverse incremental search through the history, started with CTRL+R.
+#[derive(Debug, Default, Clone)]
+pub struct HistorySearch {
+    pub term: LineEditor,
+    /// Index of the entry found.
+    pub found: Option<usize>,
+}
+
+#[test]
+fn read_back_escaped_entries() {
+    let entry = HistoryEntry {
+        at: Local::now(),
+        query: "src/* \"a\\tb\" 'c\td'\n".to_string(),
+        written: true,
+    };
+    let line = entry.to_line();
+    assert_eq!(line.matches('\t').count(), 2);
+    assert_eq!(HistoryEntry::from_line(&line).unwrap().query, entry.query);
+    assert!(HistoryEntry::from_line("not a date\t-\tx").is_none());
+}
+
+#[test]
+fn persist_and_recall() {
+    let dir = TempDir::new("persist-and-recall");
+    let path = dir.join("state").join("history");
+
+    let mut history = History::load(&path).unwrap();
+    history.push("src/* a", false).unwrap();
+    history.push("src/* a", false).unwrap();
+    history.push("src/* b c", true).unwrap();
+    history.push("src/* b c", false).unwrap();
+    history.push(" ", false).unwrap();
+    let mut history = History::load(&path).unwrap();
+    assert_eq!(history.entries().len(), 2);
+    assert!(history.entries()[1].written);
+
+    assert_eq!(history.recall_previous("typed"), Some("src/* b c"));
+    assert_eq!(history.recall_previous("ignored"), Some("src/* a"));
+    assert_eq!(history.recall_previous("ignored"), None);
+    assert_eq!(history.recall_next(), Some("src/* b c"));
+    assert_eq!(history.recall_next(), Some("typed"));
+    assert_eq!(history.recall_next(), None);
+
+    assert_eq!(history.search("src", 2), Some(1));
+    assert_eq!(history.search("src", 1), Some(0));
+    assert_eq!(history.search("b", 1), None);
+}
+
+#[test]
+fn rotate_history_file() {
+    let dir = TempDir::new("rotate-history-file");
+    let path = dir.join("history");
+    let mut history = History::load(&path).unwrap();
+    for i in 0..=2 * MAX_ENTRIES {
+        history.push(&format!("src/* {}", i), false).unwrap();
+    }
+    assert_eq!(history.entries().len(), MAX_ENTRIES);
+    let lines = || std::fs::read_to_string(&path).unwrap().lines().count();
+    assert_eq!(lines(), MAX_ENTRIES);
+    history.push("src/* last", true).unwrap();
+    assert_eq!(lines(), MAX_ENTRIES + 1);
+
+    let history = History::load(&path).unwrap();
+    assert_eq!(history.entries().len(), MAX_ENTRIES);
+    assert_eq!(
+        history.entries()[0].query,
+        format!("src/* {}", MAX_ENTRIES + 2)
+    );
+    assert!(history.entries()[MAX_ENTRIES - 1].written);
+}
@@ -0,0 +1,549 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+
+use super::decorate_file_content::DiffStyle;
+
+/// What a key does in the interactive mode. Keys bound to no action edit
+/// the prompt.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Action {
+    /// Exits, or closes the help when it is shown.
+    Quit,
+    ScrollUp,
+    ScrollDown,
+    PageUp,
+    PageDown,
+    Top,
+    Bottom,
+    NextFile,
+    PreviousFile,
+    /// Selects or unselects the first changed line in view.
+    ToggleMatch,
+    /// Folds or unfolds the first file in view.
+    ToggleFold,
+    /// Writes the selected changes to the files.
+    Write,
+    /// Restores the files as they were before the last write.
+    Undo,
+    /// Opens the first file in view in `$VISUAL` or `$EDITOR`.
+    OpenInEditor,
+    Help,
+    NextField,
+    PreviousField,
+    ToggleCompact,
+    HistoryPrevious,
+    HistoryNext,
+    HistorySearch,
+    /// Lists the syntax themes, previewing the one selected.
+    PickTheme,
+    /// Switches the previews between compact and two-line diffs.
+    ToggleDiff,
+    /// Shows or hides the first file in view next to the results, on wide
+    /// screens.
+    ToggleSplit,
+    /// Switches the search of the form between a literal and a regular
+    /// expression.
+    ToggleRegex,
+    /// Switches the form between matching and ignoring case.
+    ToggleCase,
+    /// Switches the form to matching whole words only, or back.
+    ToggleWholeWord,
+}
+
+impl Action {
+    pub const ALL: [Action; 27] = [
+        Action::Quit,
+        Action::ScrollUp,
+        Action::ScrollDown,
+        Action::PageUp,
+        Action::PageDown,
+        Action::Top,
+        Action::Bottom,
+        Action::NextFile,
+        Action::PreviousFile,
+        Action::ToggleMatch,
+        Action::ToggleFold,
+        Action::Write,
+        Action::Undo,
+        Action::OpenInEditor,
+        Action::Help,
+        Action::NextField,
+        Action::PreviousField,
+        Action::ToggleCompact,
+        Action::HistoryPrevious,
+        Action::HistoryNext,
+        Action::HistorySearch,
+        Action::PickTheme,
+        Action::ToggleDiff,
+        Action::ToggleSplit,
+        Action::ToggleRegex,
+        Action::ToggleCase,
+        Action::ToggleWholeWord,
+    ];
+
+    /// Name of the action in the `[keys]` table of the configuration.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::ScrollUp => "scroll-up",
+            Action::ScrollDown => "scroll-down",
+            Action::PageUp => "page-up",
+            Action::PageDown => "page-down",
+            Action::Top => "top",
+            Action::Bottom => "bottom",
+            Action::NextFile => "next-file",
+            Action::PreviousFile => "previous-file",
+            Action::ToggleMatch => "toggle-match",
+            Action::ToggleFold => "toggle-fold",
+            Action::Write => "write",
+            Action::Undo => "undo",
+            Action::OpenInEditor => "open-in-editor",
+            Action::Help => "help",
+            Action::NextField => "next-field",
+            Action::PreviousField => "previous-field",
+            Action::ToggleCompact => "toggle-compact",
+            Action::HistoryPrevious => "history-previous",
+            Action::HistoryNext => "history-next",
+            Action::HistorySearch => "history-search",
+            Action::PickTheme => "pick-theme",
+            Action::ToggleDiff => "toggle-diff",
+            Action::ToggleSplit => "toggle-split",
+            Action::ToggleRegex => "toggle-regex",
+            Action::ToggleCase => "toggle-case",
+            Action::ToggleWholeWord => "toggle-whole-word",
+        }
+    }
+
+    /// What the action does, as listed in the help, with the previews in
+    /// the `diff` style.
+    pub fn description(&self, diff: DiffStyle) -> &'static str {
+        match self {
+            Action::Quit => "exit, or close the help",
+            Action::ScrollUp => "scroll up",
+            Action::ScrollDown => "scroll down",
+            Action::PageUp => "scroll a page up",
+            Action::PageDown => "scroll a page down",
+            Action::Top => "go to the first result",
+            Action::Bottom => "go to the last result",
+            Action::NextFile => "go to the next file",
+            Action::PreviousFile => "go to the previous file",
+            Action::ToggleMatch => "select the first change in view",
+            Action::ToggleFold => "fold the first file in view",
+            Action::Write => "write the selected changes",
+            Action::Undo => "undo the last write",
+            Action::OpenInEditor => "open the file in view in $EDITOR",
+            Action::Help => "show or hide this help",
+            Action::NextField => "next field",
+            Action::PreviousField => "previous field",
+            Action::ToggleCompact => "switch to the compact query",
+            Action::HistoryPrevious => "previous query of the history",
+            Action::HistoryNext => "next query of the history",
+            Action::HistorySearch => "search the history",
+            Action::PickTheme => "choose the syntax theme",
+            Action::ToggleDiff => match diff {
+                DiffStyle::Compact => "switch to two-line diffs",
+                DiffStyle::TwoLine => "switch to compact diffs",
+            },
+            Action::ToggleSplit => "show the file beside the results",
+            Action::ToggleRegex => "search a regex in the form",
+            Action::ToggleCase => "ignore case in the form",
+            Action::ToggleWholeWord => "match whole words in the form",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.into_iter().find(|action| action.name() == name)
+    }
+
+    /// Whether the action changes the query, which is then searched again.
+    pub fn edits_query(&self) -> bool {
+        matches!(
+            self,
+            Action::NextField
+                | Action::PreviousField
+                | Action::ToggleCompact
+                | Action::HistoryPrevious
+                | Action::HistoryNext
+                | Action::HistorySearch
+                | Action::ToggleRegex
+                | Action::ToggleCase
+                | Action::ToggleWholeWord
+        )
+    }
+}
+
+/// A key with its modifiers, written like `ctrl+s`, `alt+v`, `f2` or
+/// `shift+tab`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct KeyBinding {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> KeyBinding {
+        // Terminals do not agree on SHIFT for characters and SHIFT+TAB:
+        // the character or BACKTAB already tells it.
+        let modifiers = match code {
+            KeyCode::Char(_) | KeyCode::BackTab => modifiers - KeyModifiers::SHIFT,
+            _ => modifiers,
+        };
+        KeyBinding { code, modifiers }
+    }
+}
+
+impl From<KeyEvent> for KeyBinding {
+    fn from(event: KeyEvent) -> KeyBinding {
+        KeyBinding::new(event.code, event.modifiers)
+    }
+}
+
+const KEY_NAMES: [(&str, KeyCode); 18] = [
+    ("esc", KeyCode::Esc),
+    ("enter", KeyCode::Enter),
+    ("tab", KeyCode::Tab),
+    ("backtab", KeyCode::BackTab),
+    ("backspace", KeyCode::Backspace),
+    ("delete", KeyCode::Delete),
+    ("insert", KeyCode::Insert),
+    ("space", KeyCode::Char(' ')),
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+    ("pgup", KeyCode::PageUp),
+    ("pgdn", KeyCode::PageDown),
+];
+
+impl FromStr for KeyBinding {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<KeyBinding, String> {
+        let invalid = || format!("invalid key {:?}", text);
+        // The key itself may be `+`, as in `ctrl++`.
+        let (modifiers, key) = match text.strip_suffix("++") {
+            Some(modifiers) => (modifiers, "+"),
+            None if text == "+" => ("", "+"),
+            None => text.rsplit_once('+').unwrap_or(("", text)),
+        };
+        let mut parsed = KeyModifiers::NONE;
+        for modifier in modifiers.split('+').filter(|m| !m.is_empty()) {
+            parsed |= match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" | "meta" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(invalid()),
+            };
+        }
+        let lower = key.to_lowercase();
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(char), None) if parsed.contains(KeyModifiers::SHIFT) => {
+                KeyCode::Char(char.to_ascii_uppercase())
+            }
+            (Some(char), None) => KeyCode::Char(char),
+            _ => match KEY_NAMES.iter().find(|(name, _)| *name == lower) {
+                Some((_, code)) => *code,
+                None => match lower.strip_prefix('f').map(|n| n.parse::<u8>()) {
+                    Some(Ok(n)) if (1..=12).contains(&n) => KeyCode::F(n),
+                    _ => return Err(invalid()),
+                },
+            },
+        };
+        let code = match code {
+            KeyCode::Tab if parsed.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+            code => code,
+        };
+        Ok(KeyBinding::new(code, parsed))
+    }
+}
+
+impl fmt::Display for KeyBinding {
+    /// Label of the key in the help line, such as `CTRL+↓` or `PgUp`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (modifier, label) in [
+            (KeyModifiers::CONTROL, "CTRL+"),
+            (KeyModifiers::ALT, "ALT+"),
+            (KeyModifiers::SHIFT, "SHIFT+"),
+        ] {
+            if self.modifiers.contains(modifier) {
+                write!(f, "{}", label)?;
+            }
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "SPACE"),
+            KeyCode::Char(char) if char.is_ascii_lowercase() => {
+                write!(f, "{}", char.to_ascii_uppercase())
+            }
+            KeyCode::Char(char) if char.is_ascii_uppercase() => write!(f, "SHIFT+{}", char),
+            KeyCode::Char(char) => write!(f, "{}", char),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            KeyCode::Up => write!(f, "↑"),
+            KeyCode::Down => write!(f, "↓"),
+            KeyCode::Left => write!(f, "←"),
+            KeyCode::Right => write!(f, "→"),
+            KeyCode::PageUp => write!(f, "PgUp"),
+            KeyCode::PageDown => write!(f, "PgDn"),
+            KeyCode::BackTab => write!(f, "SHIFT+TAB"),
+            code => write!(f, "{}", format!("{:?}", code).to_uppercase()),
+        }
+    }
+}
+
+/// Keys of the default keymap. Keys with modifiers or without a character
+/// are used, so that typing the query is never taken for an action.
+const DEFAULT_KEYS: [(&str, Action); 34] = [
+    ("esc", Action::Quit),
+    ("ctrl+c", Action::Quit),
+    ("up", Action::ScrollUp),
+    ("down", Action::ScrollDown),
+    ("pageup", Action::PageUp),
+    ("pagedown", Action::PageDown),
+    ("ctrl+home", Action::Top),
+    ("ctrl+end", Action::Bottom),
+    ("ctrl+down", Action::NextFile),
+    ("ctrl+up", Action::PreviousFile),
+    ("ctrl+t", Action::ToggleMatch),
+    ("ctrl+f", Action::ToggleFold),
+    ("ctrl+s", Action::Write),
+    ("ctrl+z", Action::Undo),
+    ("ctrl+o", Action::OpenInEditor),
+    ("f1", Action::Help),
+    ("tab", Action::NextField),
+    ("shift+tab", Action::PreviousField),
+    ("f2", Action::ToggleCompact),
+    ("ctrl+p", Action::HistoryPrevious),
+    ("ctrl+n", Action::HistoryNext),
+    ("ctrl+r", Action::HistorySearch),
+    ("f3", Action::PickTheme),
+    ("f4", Action::ToggleDiff),
+    ("f5", Action::ToggleSplit),
+    ("alt+r", Action::ToggleRegex),
+    ("alt+i", Action::ToggleCase),
+    ("alt+w", Action::ToggleWholeWord),
+    // Fallbacks for terminals without function keys.
+    ("alt+h", Action::Help),
+    ("alt+c", Action::ToggleCompact),
+    ("alt+o", Action::OpenInEditor),
+    ("alt+t", Action::PickTheme),
+    ("alt+d", Action::ToggleDiff),
+    ("alt+s", Action::ToggleSplit),
+];
+
+/// Keys the vim preset adds to the default ones. CTRL+E and CTRL+U keep
+/// moving to the end of the prompt and deleting to its start.
+const VIM_KEYS: [(&str, Action); 9] = [
+    ("ctrl+j", Action::ScrollDown),
+    ("ctrl+k", Action::ScrollUp),
+    ("ctrl+y", Action::ScrollUp),
+    ("ctrl+d", Action::PageDown),
+    ("ctrl+b", Action::PageUp),
+    ("alt+g", Action::Top),
+    ("alt+shift+g", Action::Bottom),
+    ("alt+j", Action::NextFile),
+    ("alt+k", Action::PreviousFile),
+];
+
+/// Keys the emacs preset adds to the default ones. The history moves to
+/// ALT+P and ALT+N, as in the minibuffer.
+const EMACS_KEYS: [(&str, Action); 8] = [
+    ("ctrl+n", Action::ScrollDown),
+    ("ctrl+p", Action::ScrollUp),
+    ("ctrl+v", Action::PageDown),
+    ("alt+v", Action::PageUp),
+    ("alt+<", Action::Top),
+    ("alt+>", Action::Bottom),
+    ("alt+n", Action::HistoryNext),
+    ("alt+p", Action::HistoryPrevious),
+];
+
+/// Actions of the keys, in the order they are listed in the help.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Keymap {
+    bindings: Vec<(KeyBinding, Action)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        let mut keymap = Keymap { bindings: vec![] };
+        keymap.bind_all(&DEFAULT_KEYS);
+        keymap
+    }
+}
+
+impl Keymap {
+    pub const PRESETS: [&'static str; 3] = ["default", "vim", "emacs"];
+
+    /// The default keymap with the keys of the preset `name` added.
+    pub fn preset(name: &str) -> Option<Keymap> {
+        let mut keymap = Keymap::default();
+        match name {
+            "default" => (),
+            "vim" => keymap.bind_all(&VIM_KEYS),
+            "emacs" => keymap.bind_all(&EMACS_KEYS),
+            _ => return None,
+        }
+        Some(keymap)
+    }
+
+    fn bind_all(&mut self, keys: &[(&str, Action)]) {
+        for (key, action) in keys {
+            match key.parse() {
+                Ok(key) => self.bind(key, *action),
+                Err(e) => unreachable!("{}", e),
+            }
+        }
+    }
+
+    /// Binds `key` to `action`, in place of the action it was bound to.
+    pub fn bind(&mut self, key: KeyBinding, action: Action) {
+        self.bindings.retain(|(bound, _)| *bound != key);
+        self.bindings.push((key, action));
+    }
+
+    /// Replaces the keys of `action` by `keys`; no keys leave it unbound.
+    pub fn rebind(&mut self, action: Action, keys: &[KeyBinding]) {
+        self.bindings.retain(|(_, bound)| *bound != action);
+        for key in keys {
+            self.bind(*key, action);
+        }
+    }
+
+    /// Action of a key press, ignoring releases.
+    pub fn action(&self, event: KeyEvent) -> Option<Action> {
+        if event.kind == KeyEventKind::Release {
+            return None;
+        }
+        let key = KeyBinding::from(event);
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == key)
+            .map(|(_, action)| *action)
+    }
+
+    pub fn keys(&self, action: Action) -> Vec<KeyBinding> {
+        self.bindings
+            .iter()
+            .filter(|(_, bound)| *bound == action)
+            .map(|(key, _)| *key)
+            .collect()
+    }
+
+    /// Keys and description of every action, as listed in the help.
+    pub fn help_entries(&self, diff: DiffStyle) -> Vec<(String, &'static str)> {
+        Action::ALL
+            .iter()
+            .map(|action| {
+                let keys = self
+                    .keys(*action)
+                    .iter()
+                    .map(|key| key.to_string())
+                    .collect::<Vec<String>>();
+                (keys.join(" "), action.description(diff))
+            })
+            .collect()
+    }
+
+    /// Label of the first key of `action`, if it has one.
+    pub fn label(&self, action: Action) -> Option<String> {
+        self.keys(action).first().map(|key| key.to_string())
+    }
+}
+
+#[test]
+fn parse_and_display_keys() {
+    let parse = |text: &str| text.parse::<KeyBinding>();
+    assert_eq!(
+        parse("ctrl+s"),
+        Ok(KeyBinding::new(KeyCode::Char('s'), KeyModifiers::CONTROL))
+    );
+    assert_eq!(
+        parse("Alt+Shift+G"),
+        Ok(KeyBinding::new(KeyCode::Char('G'), KeyModifiers::ALT))
+    );
+    assert_eq!(parse("shift+tab"), parse("backtab"));
+    assert_eq!(
+        parse("ctrl++"),
+        Ok(KeyBinding::new(KeyCode::Char('+'), KeyModifiers::CONTROL))
+    );
+    assert_eq!(
+        parse("F12"),
+        Ok(KeyBinding::new(KeyCode::F(12), KeyModifiers::NONE))
+    );
+    assert!(parse("hyper+x").is_err());
+    assert!(parse("f13").is_err());
+    assert!(parse("").is_err());
+
+    for text in [
+        "ctrl+down",
+        "pageup",
+        "esc",
+        "alt+<",
+        "shift+tab",
+        "f1",
+        "alt+shift+g",
+    ] {
+        let key = parse(text).unwrap();
+        assert_eq!(parse(&key.to_string().replace('↓', "down")), Ok(key));
+    }
+    assert_eq!(parse("ctrl+down").unwrap().to_string(), "CTRL+↓");
+}
+
+#[test]
+fn bind_presets_and_overrides() {
+    let press = |code: KeyCode, modifiers: KeyModifiers| KeyEvent::new(code, modifiers);
+    let keymap = Keymap::default();
+    assert_eq!(
+        keymap.action(press(KeyCode::Esc, KeyModifiers::NONE)),
+        Some(Action::Quit)
+    );
+    assert_eq!(
+        keymap.action(press(KeyCode::BackTab, KeyModifiers::SHIFT)),
+        Some(Action::PreviousField)
+    );
+    assert_eq!(
+        keymap.action(press(KeyCode::Char('j'), KeyModifiers::NONE)),
+        None
+    );
+    assert_eq!(keymap.label(Action::Write).as_deref(), Some("CTRL+S"));
+
+    let emacs = Keymap::preset("emacs").unwrap();
+    let ctrl_n = press(KeyCode::Char('n'), KeyModifiers::CONTROL);
+    assert_eq!(emacs.action(ctrl_n), Some(Action::ScrollDown));
+    assert_eq!(emacs.label(Action::HistoryNext).as_deref(), Some("ALT+N"));
+    let vim = Keymap::preset("vim").unwrap();
+    let bottom = press(KeyCode::Char('G'), KeyModifiers::ALT | KeyModifiers::SHIFT);
+    assert_eq!(vim.action(bottom), Some(Action::Bottom));
+    assert!(Keymap::preset("nano").is_none());
+
+    let mut keymap = Keymap::default();
+    keymap.rebind(Action::Write, &["f10".parse().unwrap()]);
+    keymap.rebind(Action::Undo, &[]);
+    assert_eq!(keymap.label(Action::Write).as_deref(), Some("F10"));
+    assert_eq!(keymap.keys(Action::Undo), vec![]);
+    assert_eq!(
+        keymap.action(press(KeyCode::Char('s'), KeyModifiers::CONTROL)),
+        None
+    );
+    assert!(Action::ALL
+        .iter()
+        .all(|action| Action::from_name(action.name()) == Some(*action)));
+}
+
+#[test]
+fn describe_diff_toggle_by_style() {
+    assert_eq!(
+        Action::ToggleDiff.description(DiffStyle::Compact),
+        "switch to two-line diffs"
+    );
+    assert_eq!(
+        Action::ToggleDiff.description(DiffStyle::TwoLine),
+        "switch to compact diffs"
+    );
+}
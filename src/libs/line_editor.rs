@@ -0,0 +1,220 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Single line of editable text with a cursor, moved by grapheme so that
+/// accents and emoji are edited as one character.
+#[derive(Debug, PartialEq, Default, Clone)]
+pub struct LineEditor {
+    text: String,
+    /// Byte offset of the cursor, always on a grapheme boundary.
+    cursor: usize,
+}
+
+impl LineEditor {
+    /// Starts with the cursor at the end of `text`.
+    pub fn new(text: &str) -> LineEditor {
+        LineEditor {
+            text: text.to_string(),
+            cursor: text.len(),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Screen column of the cursor, counting wide characters twice.
+    pub fn cursor_column(&self) -> usize {
+        self.text[..self.cursor].width()
+    }
+
+    /// Inserts `text` at the cursor. Line breaks, from a paste, become spaces
+    /// since the query is a single line.
+    pub fn insert(&mut self, text: &str) {
+        let text = text.replace("\r\n", " ").replace(['\n', '\r'], " ");
+        self.text.insert_str(self.cursor, &text);
+        self.cursor += text.len();
+    }
+
+    pub fn move_left(&mut self) {
+        self.cursor = self.previous_boundary(self.cursor);
+    }
+
+    pub fn move_right(&mut self) {
+        self.cursor = self.next_boundary(self.cursor);
+    }
+
+    pub fn move_home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn move_end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    pub fn move_word_left(&mut self) {
+        self.cursor = self.previous_word(self.cursor);
+    }
+
+    pub fn move_word_right(&mut self) {
+        let rest = &self.text[self.cursor..];
+        let start = rest.len() - rest.trim_start().len();
+        self.cursor += match rest[start..].find(char::is_whitespace) {
+            Some(end) => start + end,
+            None => rest.len(),
+        };
+    }
+
+    /// Deletes the grapheme before the cursor.
+    pub fn delete_backward(&mut self) {
+        let start = self.previous_boundary(self.cursor);
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+
+    /// Deletes the grapheme under the cursor.
+    pub fn delete_forward(&mut self) {
+        let end = self.next_boundary(self.cursor);
+        self.text.replace_range(self.cursor..end, "");
+    }
+
+    /// Deletes the word before the cursor and the spaces after it.
+    pub fn delete_word_backward(&mut self) {
+        let start = self.previous_word(self.cursor);
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+
+    pub fn delete_to_start(&mut self) {
+        self.text.replace_range(..self.cursor, "");
+        self.cursor = 0;
+    }
+
+    /// Applies an editing key. Returns whether the key was used.
+    pub fn handle_key(&mut self, event: KeyEvent) -> bool {
+        if event.kind == KeyEventKind::Release {
+            return false;
+        }
+        let control = event.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = event.modifiers.contains(KeyModifiers::ALT);
+        match event.code {
+            KeyCode::Char('a') if control => self.move_home(),
+            KeyCode::Char('e') if control => self.move_end(),
+            KeyCode::Char('w') if control => self.delete_word_backward(),
+            KeyCode::Char('u') if control => self.delete_to_start(),
+            KeyCode::Char(_) if control || alt => return false,
+            KeyCode::Char(c) => self.insert(c.encode_utf8(&mut [0; 4])),
+            KeyCode::Backspace if alt || control => self.delete_word_backward(),
+            KeyCode::Backspace => self.delete_backward(),
+            KeyCode::Delete => self.delete_forward(),
+            KeyCode::Left if control || alt => self.move_word_left(),
+            KeyCode::Right if control || alt => self.move_word_right(),
+            KeyCode::Left => self.move_left(),
+            KeyCode::Right => self.move_right(),
+            KeyCode::Home if !control => self.move_home(),
+            KeyCode::End if !control => self.move_end(),
+            _ => return false,
+        }
+        true
+    }
+
+    fn previous_boundary(&self, offset: usize) -> usize {
+        self.text[..offset]
+            .grapheme_indices(true)
+            .next_back()
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    }
+
+    fn next_boundary(&self, offset: usize) -> usize {
+        self.text[offset..]
+            .graphemes(true)
+            .next()
+            .map(|grapheme| offset + grapheme.len())
+            .unwrap_or(offset)
+    }
+
+    /// Start of the word before `offset`, skipping the spaces in between.
+    fn previous_word(&self, offset: usize) -> usize {
+        let before = self.text[..offset].trim_end();
+        match before.rfind(char::is_whitespace) {
+            Some(i) => i + before[i..].chars().next().map_or(1, char::len_utf8),
+            None => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+    KeyEvent::new(code, modifiers)
+}
+
+#[test]
+fn edit_in_the_middle() {
+    let mut editor = LineEditor::new("src/* fn");
+    editor.move_home();
+    editor.move_right();
+    editor.move_right();
+    editor.move_right();
+    editor.insert("s");
+    assert_eq!(editor.text(), "srcs/* fn");
+    editor.delete_backward();
+    editor.delete_forward();
+    assert_eq!(editor.text(), "src* fn");
+    assert_eq!(editor.cursor(), 3);
+}
+
+#[test]
+fn move_by_grapheme() {
+    let mut editor = LineEditor::new("e\u{301}t\u{e9}");
+    editor.move_left();
+    assert_eq!(editor.cursor(), 4);
+    editor.move_left();
+    editor.move_left();
+    assert_eq!(editor.cursor(), 0);
+    editor.delete_forward();
+    assert_eq!(editor.text(), "t\u{e9}");
+
+    let mut editor = LineEditor::new("日本 x");
+    editor.move_left();
+    editor.move_left();
+    assert_eq!(editor.cursor_column(), 4);
+}
+
+#[test]
+fn delete_words() {
+    let mut editor = LineEditor::new("src/* old  new  ");
+    editor.delete_word_backward();
+    assert_eq!(editor.text(), "src/* old  ");
+    editor.move_word_left();
+    assert_eq!(editor.cursor(), 6);
+    editor.move_word_right();
+    assert_eq!(editor.cursor(), 9);
+    assert!(editor.handle_key(key(KeyCode::Backspace, KeyModifiers::ALT)));
+    assert_eq!(editor.text(), "src/*   ");
+    assert!(editor.handle_key(key(KeyCode::Char('u'), KeyModifiers::CONTROL)));
+    assert_eq!(editor.text(), "  ");
+}
+
+#[test]
+fn handle_editing_keys() {
+    let mut editor = LineEditor::default();
+    assert!(editor.handle_key(key(KeyCode::Char('b'), KeyModifiers::NONE)));
+    assert!(editor.handle_key(key(KeyCode::Char('C'), KeyModifiers::SHIFT)));
+    assert!(editor.handle_key(key(KeyCode::Char('a'), KeyModifiers::CONTROL)));
+    assert!(editor.handle_key(key(KeyCode::Char('a'), KeyModifiers::NONE)));
+    assert!(editor.handle_key(key(KeyCode::End, KeyModifiers::NONE)));
+    assert!(!editor.handle_key(key(KeyCode::End, KeyModifiers::CONTROL)));
+    assert!(!editor.handle_key(key(KeyCode::Down, KeyModifiers::NONE)));
+    assert!(!editor.handle_key(key(KeyCode::Char('c'), KeyModifiers::CONTROL)));
+    assert_eq!(editor.text(), "abC");
+    assert_eq!(editor.cursor(), 3);
+
+    editor.insert("one\r\ntwo\n");
+    assert_eq!(editor.text(), "abCone two ");
+}
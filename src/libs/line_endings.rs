@@ -0,0 +1,162 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+use super::matcher::Matcher;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Mixed,
+    None,
+}
+
+impl LineEnding {
+    pub fn detect(content: &str) -> LineEnding {
+        let mut lf = 0;
+        let mut crlf = 0;
+        for (i, _) in content.match_indices('\n') {
+            if i > 0 && content.as_bytes()[i - 1] == b'\r' {
+                crlf += 1;
+            } else {
+                lf += 1;
+            }
+        }
+        match (lf, crlf) {
+            (0, 0) => LineEnding::None,
+            (_, 0) => LineEnding::Lf,
+            (0, _) => LineEnding::CrLf,
+            _ => LineEnding::Mixed,
+        }
+    }
+
+    /// Converts the `\n` of a normalised text to this line ending.
+    /// Mixed files keep `\n` since there is no single style to follow.
+    pub fn apply(&self, text: &str) -> String {
+        match self {
+            LineEnding::CrLf => normalize(text).replace('\n', "\r\n"),
+            _ => text.to_string(),
+        }
+    }
+}
+
+pub fn normalize(text: &str) -> String {
+    text.replace("\r\n", "\n")
+}
+
+/// Replaces the given ranges of `content` by the text `substitute` returns
+/// for them, leaving every other byte (line endings and final newline
+/// included) untouched.
+pub fn replace_matches<'a>(
+    content: &str,
+    matches: &[Range<usize>],
+    mut substitute: impl FnMut(&Range<usize>) -> Cow<'a, str>,
+) -> String {
+    let mut result = String::with_capacity(content.len());
+    let mut last = 0;
+    for range in matches {
+        result.push_str(&content[last..range.start]);
+        result.push_str(&substitute(range));
+        last = range.end;
+    }
+    result.push_str(&content[last..]);
+    result
+}
+
+/// Replaces `query` by `substitute` in `content` while preserving the
+/// file's line endings.
+pub fn replace_preserving_endings(
+    content: &str,
+    matcher: &Matcher,
+    substitute: &str,
+) -> (String, usize) {
+    let matches = matcher.find_all(content);
+    (
+        replace_ranges_preserving_endings(content, &matches, matcher, substitute),
+        matches.len(),
+    )
+}
+
+/// Replaces the given ranges of `content` by `substitute` as `matcher`
+/// expands it, its line breaks written like those of `content` when the
+/// matcher is end-of-line insensitive. In files with mixed line endings,
+/// each substitute follows the match it replaces.
+pub fn replace_ranges_preserving_endings(
+    content: &str,
+    matches: &[Range<usize>],
+    matcher: &Matcher,
+    substitute: &str,
+) -> String {
+    let file_ending = match matcher.eol_insensitive() {
+        true => LineEnding::detect(content),
+        false => LineEnding::None,
+    };
+    let plain = file_ending.apply(substitute);
+    replace_matches(content, matches, |range| {
+        let ending = match file_ending {
+            LineEnding::Mixed => match_ending(content, range),
+            ending => ending,
+        };
+        match matcher.substitute(content, range, substitute) {
+            Cow::Borrowed(_) if ending == file_ending => Cow::Borrowed(plain.as_str()),
+            expanded => Cow::Owned(ending.apply(&expanded)),
+        }
+    })
+}
+
+/// Line ending of the match at `range` of `content`: CRLF if it contains
+/// one, LF if it contains only LF, otherwise that of the line it ends on.
+fn match_ending(content: &str, range: &Range<usize>) -> LineEnding {
+    match LineEnding::detect(&content[range.clone()]) {
+        LineEnding::None => {}
+        LineEnding::Lf => return LineEnding::Lf,
+        _ => return LineEnding::CrLf,
+    }
+    match memchr::memchr(b'\n', &content.as_bytes()[range.end..]) {
+        Some(i) if content[..range.end + i].ends_with('\r') => LineEnding::CrLf,
+        Some(_) => LineEnding::Lf,
+        None => LineEnding::Mixed,
+    }
+}
+
+#[test]
+fn detect_line_endings() {
+    assert_eq!(LineEnding::detect("a\nb\n"), LineEnding::Lf);
+    assert_eq!(LineEnding::detect("a\r\nb\r\n"), LineEnding::CrLf);
+    assert_eq!(LineEnding::detect("a\r\nb\n"), LineEnding::Mixed);
+    assert_eq!(LineEnding::detect("a"), LineEnding::None);
+}
+
+#[test]
+fn preserve_endings_on_replace() {
+    let (result, count) = replace_preserving_endings("a\r\nb\r\nc", &Matcher::new("b", false), "x");
+    assert_eq!((result.as_str(), count), ("a\r\nx\r\nc", 1));
+
+    let (result, count) =
+        replace_preserving_endings("a\r\nb\r\n", &Matcher::new("a\nb", true), "y\nz");
+    assert_eq!((result.as_str(), count), ("y\r\nz\r\n", 1));
+
+    let (result, _) = replace_preserving_endings("a\nb\r\n", &Matcher::new("a\nb", true), "c");
+    assert_eq!(result, "c\r\n");
+    assert!(
+        !replace_preserving_endings("a\nb", &Matcher::new("b", true), "c")
+            .0
+            .ends_with('\n')
+    );
+}
+
+#[test]
+fn follow_each_match_in_mixed_endings() {
+    let content = "a\r\nb\na\nb\r\nc\r\nd\ne";
+    let matcher = Matcher::new("a\nb", true);
+    let (result, count) = replace_preserving_endings(content, &matcher, "x\ny");
+    assert_eq!(count, 2);
+    assert_eq!(result, "x\r\ny\nx\ny\r\nc\r\nd\ne");
+
+    let (result, _) = replace_preserving_endings(content, &Matcher::new("c", true), "x\ny");
+    assert_eq!(result, "a\r\nb\na\nb\r\nx\r\ny\r\nd\ne");
+    let (result, _) = replace_preserving_endings(content, &Matcher::new("d", true), "x\ny");
+    assert!(result.ends_with("\r\nx\ny\ne"));
+    let (result, _) = replace_preserving_endings(content, &Matcher::new("e", true), "x\ny");
+    assert!(result.ends_with("\nx\ny"));
+}
@@ -0,0 +1,360 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+use aho_corasick::{AhoCorasick, MatchKind};
+use memchr::memmem::Finder;
+use regex::{Regex, RegexBuilder};
+
+use super::line_endings::normalize;
+use super::search::SearchOptions;
+
+/// Above this many line breaks, an end-of-line insensitive query is matched
+/// by verifying each line break instead of expanding every LF/CRLF variant.
+const MAX_VARIANT_BREAKS: usize = 6;
+
+enum Strategy {
+    Empty,
+    Literal(Finder<'static>),
+    Variants(AhoCorasick),
+    Lines(Vec<String>, Option<Finder<'static>>),
+    /// Whether the substitute refers to capture groups, for queries that
+    /// are regular expressions.
+    Regex(Regex, bool),
+}
+
+/// Finds the non-overlapping byte ranges of a query in a whole buffer in a
+/// single pass, using SIMD-accelerated literal search.
+pub struct Matcher {
+    strategy: Strategy,
+    eol_insensitive: bool,
+}
+
+impl Matcher {
+    /// When `eol_insensitive` is set, a line break in the query matches
+    /// both `\n` and `\r\n` in the content.
+    pub fn new(query: &str, eol_insensitive: bool) -> Matcher {
+        let strategy = if query.is_empty() {
+            Strategy::Empty
+        } else if !eol_insensitive || !query.contains('\n') {
+            Strategy::Literal(Finder::new(query.as_bytes()).into_owned())
+        } else {
+            let parts = normalize(query)
+                .split('\n')
+                .map(|part| part.to_string())
+                .collect::<Vec<String>>();
+            if parts.len() - 1 <= MAX_VARIANT_BREAKS {
+                match AhoCorasick::builder()
+                    .match_kind(MatchKind::LeftmostLongest)
+                    .build(line_break_variants(&parts))
+                {
+                    Ok(automaton) => Strategy::Variants(automaton),
+                    Err(_e) => lines_strategy(parts),
+                }
+            } else {
+                lines_strategy(parts)
+            }
+        };
+        Matcher {
+            strategy,
+            eol_insensitive,
+        }
+    }
+
+    /// Matches `query` with the options of a search. Regular expressions,
+    /// case insensitive and whole word queries go through the regex engine,
+    /// which rejects invalid regular expressions.
+    pub fn with_options(query: &str, options: &SearchOptions) -> Result<Matcher, regex::Error> {
+        if !options.regex && !options.case_insensitive && !options.whole_word {
+            return Ok(Matcher::new(query, options.eol_insensitive));
+        }
+        let strategy = match query.is_empty() {
+            true => Strategy::Empty,
+            false => Strategy::Regex(build_regex(query, options)?, options.regex),
+        };
+        Ok(Matcher {
+            strategy,
+            eol_insensitive: options.eol_insensitive,
+        })
+    }
+
+    pub fn eol_insensitive(&self) -> bool {
+        self.eol_insensitive
+    }
+
+    pub fn find_all(&self, content: &str) -> Vec<Range<usize>> {
+        match &self.strategy {
+            Strategy::Empty => vec![],
+            Strategy::Literal(finder) => {
+                let len = finder.needle().len();
+                finder
+                    .find_iter(content.as_bytes())
+                    .map(|start| start..start + len)
+                    .collect()
+            }
+            Strategy::Variants(automaton) => automaton
+                .find_iter(content)
+                .map(|found| found.range())
+                .collect(),
+            Strategy::Lines(parts, first) => find_lines(content, parts, first.as_ref()),
+            // Empty matches are kept as by `Regex::replace_all`, since `^`
+            // or `$` insert text, but for the one after a final line break
+            // where no line starts.
+            Strategy::Regex(regex, _) => regex
+                .find_iter(content)
+                .filter(|found| {
+                    !found.is_empty() || found.start() < content.len() || !content.ends_with('\n')
+                })
+                .map(|found| found.range())
+                .collect(),
+        }
+    }
+
+    /// The text replacing the match at `range` of `content`: `substitute`
+    /// with `$1`, `${name}` and the like expanded as by `Regex::replace` for
+    /// regular expressions, as it is otherwise.
+    pub fn substitute<'a>(
+        &self,
+        content: &str,
+        range: &Range<usize>,
+        substitute: &'a str,
+    ) -> Cow<'a, str> {
+        let regex = match &self.strategy {
+            Strategy::Regex(regex, true) if substitute.contains('$') => regex,
+            _ => return Cow::Borrowed(substitute),
+        };
+        match regex.captures_at(content, range.start) {
+            Some(captures) => {
+                let mut expanded = String::new();
+                captures.expand(substitute, &mut expanded);
+                Cow::Owned(expanded)
+            }
+            None => Cow::Borrowed(substitute),
+        }
+    }
+}
+
+fn build_regex(query: &str, options: &SearchOptions) -> Result<Regex, regex::Error> {
+    let mut pattern = match options.regex {
+        true => query.to_string(),
+        false => regex::escape(query),
+    };
+    if options.eol_insensitive {
+        pattern = normalize(&pattern).replace('\n', "\r?\n");
+    }
+    if options.whole_word {
+        pattern = format!(r"\b(?:{})\b", pattern);
+    }
+    RegexBuilder::new(&pattern)
+        .case_insensitive(options.case_insensitive)
+        .multi_line(true)
+        .crlf(true)
+        .build()
+}
+
+fn lines_strategy(parts: Vec<String>) -> Strategy {
+    let first = match parts[0].is_empty() {
+        true => None,
+        false => Some(Finder::new(parts[0].as_bytes()).into_owned()),
+    };
+    Strategy::Lines(parts, first)
+}
+
+fn line_break_variants(parts: &[String]) -> Vec<String> {
+    let mut variants = vec![parts[0].clone()];
+    for part in &parts[1..] {
+        variants = variants
+            .iter()
+            .flat_map(|variant| {
+                [
+                    format!("{}\n{}", variant, part),
+                    format!("{}\r\n{}", variant, part),
+                ]
+            })
+            .collect();
+    }
+    variants
+}
+
+fn match_at(content: &str, at: usize, parts: &[String]) -> Option<usize> {
+    let mut position = at;
+    for (i, part) in parts.iter().enumerate() {
+        if !content[position..].starts_with(part.as_str()) {
+            return None;
+        }
+        position += part.len();
+        if i == parts.len() - 1 {
+            break;
+        }
+        if content[position..].starts_with("\r\n") {
+            position += 2;
+        } else if content[position..].starts_with('\n') {
+            position += 1;
+        } else {
+            return None;
+        }
+    }
+    Some(position)
+}
+
+fn find_lines(content: &str, parts: &[String], first: Option<&Finder>) -> Vec<Range<usize>> {
+    let bytes = content.as_bytes();
+    let candidates: Vec<usize> = match first {
+        Some(finder) => finder.find_iter(bytes).collect(),
+        None => memchr::memchr_iter(b'\n', bytes)
+            .map(|i| match i > 0 && bytes[i - 1] == b'\r' {
+                true => i - 1,
+                false => i,
+            })
+            .collect(),
+    };
+
+    let mut matches = vec![];
+    let mut last_end = 0;
+    for start in candidates {
+        if start < last_end {
+            continue;
+        }
+        if let Some(end) = match_at(content, start, parts) {
+            matches.push(start..end);
+            last_end = end;
+        }
+    }
+    matches
+}
+
+#[test]
+fn find_literal() {
+    let matcher = Matcher::new("ab", false);
+    assert_eq!(matcher.find_all("abcabab"), vec![0..2, 3..5, 5..7]);
+    assert!(Matcher::new("", false).find_all("abc").is_empty());
+}
+
+#[test]
+fn find_crlf_query() {
+    let content = "a\r\nb\r\na\nb";
+    assert_eq!(Matcher::new("a\r\nb", false).find_all(content), vec![0..4]);
+    assert_eq!(Matcher::new("a\nb", false).find_all(content), vec![6..9]);
+    assert_eq!(
+        Matcher::new("a\nb", true).find_all(content),
+        vec![0..4, 6..9]
+    );
+    assert_eq!(
+        Matcher::new("\nb", true).find_all(content),
+        vec![1..4, 7..9]
+    );
+}
+
+#[test]
+fn find_many_line_breaks() {
+    let query = "a\n".repeat(MAX_VARIANT_BREAKS + 2);
+    let content = format!("x{}y", "a\r\n".repeat(MAX_VARIANT_BREAKS + 2));
+    assert_eq!(
+        Matcher::new(&query, true).find_all(&content),
+        vec![1..content.len() - 1]
+    );
+    assert!(Matcher::new(&format!("\n{}", query), true)
+        .find_all(&format!("\r\n{}", content))
+        .is_empty());
+}
+
+#[test]
+fn find_with_options() {
+    let find = |query: &str, options: SearchOptions| {
+        Matcher::with_options(query, &options)
+            .unwrap()
+            .find_all("Fn fn fnord f(n)\r\nx")
+    };
+    let options = SearchOptions::default();
+    assert_eq!(
+        find(
+            "fn",
+            SearchOptions {
+                case_insensitive: true,
+                ..options.clone()
+            }
+        ),
+        vec![0..2, 3..5, 6..8]
+    );
+    assert_eq!(
+        find(
+            "fn",
+            SearchOptions {
+                whole_word: true,
+                ..options.clone()
+            }
+        ),
+        vec![3..5]
+    );
+    assert_eq!(
+        find(
+            r"f\(?n\)?\s",
+            SearchOptions {
+                regex: true,
+                ..options.clone()
+            }
+        ),
+        vec![3..6, 12..17]
+    );
+    assert_eq!(
+        find(
+            "n\\)\n",
+            SearchOptions {
+                regex: true,
+                eol_insensitive: true,
+                ..options.clone()
+            }
+        ),
+        vec![14..18]
+    );
+    assert_eq!(
+        find(
+            "(n)\nx",
+            SearchOptions {
+                eol_insensitive: true,
+                case_insensitive: true,
+                ..options.clone()
+            }
+        ),
+        vec![13..19]
+    );
+    let invalid = SearchOptions {
+        regex: true,
+        ..options.clone()
+    };
+    assert!(Matcher::with_options("(", &invalid).is_err());
+    let empty = find(
+        "x*",
+        SearchOptions {
+            regex: true,
+            ..options
+        },
+    );
+    assert_eq!(empty[..2], [0..0, 1..1]);
+    assert!(empty.contains(&(18..19)));
+}
+
+#[test]
+fn expand_captures_in_substitute() {
+    let substitute = |query: &str, options: SearchOptions, substitute: &str| {
+        let content = "to: ann@example, $5";
+        let matcher = Matcher::with_options(query, &options).unwrap();
+        let matches = matcher.find_all(content);
+        matches
+            .iter()
+            .map(|range| matcher.substitute(content, range, substitute).into_owned())
+            .collect::<Vec<String>>()
+    };
+    let regex = SearchOptions {
+        regex: true,
+        ..SearchOptions::default()
+    };
+    assert_eq!(
+        substitute(r"(\w+)@(?<host>\w+)", regex.clone(), "${host} ($1) $$"),
+        vec!["example (ann) $"]
+    );
+    let literal = SearchOptions {
+        case_insensitive: true,
+        ..SearchOptions::default()
+    };
+    assert_eq!(substitute("$5", literal, "$1"), vec!["$1"]);
+}
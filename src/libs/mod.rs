@@ -4,3 +4,19 @@ pub mod scrollbar;
 pub mod terminal;
 pub mod state;
 pub mod syntax_highlight;
+pub mod line_endings;
+pub mod file_store;
+pub mod matcher;
+pub mod search;
+pub mod replacer;
+pub mod renderer;
+pub mod screen;
+pub mod line_editor;
+pub mod prompt;
+pub mod history;
+pub mod config;
+pub mod keymap;
+pub mod theme;
+#[cfg(test)]
+pub mod temp_dir;
+pub mod cli;
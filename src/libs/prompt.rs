@@ -0,0 +1,283 @@
+use super::line_editor::LineEditor;
+use super::split_query::{split_query, Modifiers, QuerySplit};
+
+#[derive(Debug, PartialEq, Default, Clone, Copy)]
+pub enum Field {
+    Glob,
+    #[default]
+    Search,
+    Replace,
+}
+
+impl Field {
+    pub const ALL: [Field; 3] = [Field::Glob, Field::Search, Field::Replace];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Field::Glob => "glob",
+            Field::Search => "search",
+            Field::Replace => "replace",
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Query input, either a form with a field per token or the compact single
+/// line `<glob> <query> <replacement>`. Fields are taken literally, so they
+/// may contain quotes and spaces.
+#[derive(Debug, PartialEq, Default, Clone)]
+pub struct Prompt {
+    pub compact: bool,
+    pub line: LineEditor,
+    pub fields: [LineEditor; 3],
+    pub focus: Field,
+    /// Whether the form replaces the matches, with nothing while the replace
+    /// field is empty: once that field was focused or given a value.
+    pub replacing: bool,
+    /// Regex, case and whole word modes of the form.
+    pub modifiers: Modifiers,
+}
+
+impl Prompt {
+    pub fn new(query: &str, compact: bool) -> Prompt {
+        let mut prompt = Prompt {
+            compact,
+            line: LineEditor::new(query),
+            ..Prompt::default()
+        };
+        prompt.set_form(query);
+        prompt
+    }
+
+    pub fn field(&self, field: Field) -> &LineEditor {
+        &self.fields[field.index()]
+    }
+
+    /// Editor receiving the keys.
+    pub fn editor(&self) -> &LineEditor {
+        match self.compact {
+            true => &self.line,
+            false => self.field(self.focus),
+        }
+    }
+
+    pub fn editor_mut(&mut self) -> &mut LineEditor {
+        match self.compact {
+            true => &mut self.line,
+            false => &mut self.fields[self.focus.index()],
+        }
+    }
+
+    /// Moves to the next field, or back by one with `backward`. Returns
+    /// false in compact mode.
+    pub fn cycle_focus(&mut self, backward: bool) -> bool {
+        if self.compact {
+            return false;
+        }
+        let step = if backward { 2 } else { 1 };
+        self.focus = Field::ALL[(self.focus.index() + step) % 3];
+        self.replacing |= self.focus == Field::Replace;
+        true
+    }
+
+    /// Switches the mode of the form `mode` picks among its modifiers.
+    /// Returns false in compact mode, where modifiers are typed.
+    pub fn toggle_mode(&mut self, mode: fn(&mut Modifiers) -> &mut bool) -> bool {
+        if self.compact {
+            return false;
+        }
+        let active = mode(&mut self.modifiers);
+        *active = !*active;
+        true
+    }
+
+    /// Switches between the form and the compact line, carrying the query.
+    pub fn toggle_compact(&mut self) {
+        let query = self.query();
+        self.compact = !self.compact;
+        self.set_query(&query);
+    }
+
+    /// The query as a compact line, the fields of the form being quoted.
+    pub fn query(&self) -> String {
+        self.split().query.unwrap_or_default()
+    }
+
+    /// Replaces the query, keeping the focused field of the form.
+    pub fn set_query(&mut self, query: &str) {
+        match self.compact {
+            true => self.line = LineEditor::new(query),
+            false => self.set_form(query),
+        }
+    }
+
+    /// Tokens of the query. In the form, an empty replace field replaces
+    /// matches with nothing once it was focused or given a value.
+    pub fn split(&self) -> QuerySplit {
+        if self.compact {
+            return split_query(self.line.text());
+        }
+        let text = |field: Field| self.field(field).text().to_string();
+        let non_empty = |field: Field| Some(text(field)).filter(|text| !text.is_empty());
+        let replace = match self.replacing {
+            true => Some(text(Field::Replace)),
+            false => non_empty(Field::Replace),
+        };
+        let tokens = [non_empty(Field::Glob), non_empty(Field::Search), replace];
+        QuerySplit {
+            query: Some(self.form_line(&tokens)),
+            glob: tokens[0].clone(),
+            search: tokens[1].clone(),
+            replace: tokens[2].clone(),
+            modifiers: self.modifiers.clone(),
+            ..QuerySplit::default()
+        }
+    }
+
+    /// Fills the form with the tokens and modes of `query`.
+    fn set_form(&mut self, query: &str) {
+        let split = split_query(query);
+        self.replacing = split.replace.is_some();
+        self.modifiers = Modifiers {
+            case_insensitive: split.modifiers.case_insensitive,
+            whole_word: split.modifiers.whole_word,
+            regex: split.modifiers.regex,
+            ..Modifiers::default()
+        };
+        self.fields = [split.glob, split.search, split.replace]
+            .map(|token| LineEditor::new(&token.unwrap_or_default()));
+    }
+
+    /// The form as a compact line: its tokens quoted, the search written as
+    /// a `/regex/` in regex mode, then the `-i` and `-w` modifiers.
+    fn form_line(&self, tokens: &[Option<String>]) -> String {
+        let mut words = tokens
+            .iter()
+            .map_while(|token| token.as_deref())
+            .enumerate()
+            .map(|(i, token)| match i == 1 && self.modifiers.regex {
+                true => regex_token(token),
+                false => quote(token),
+            })
+            .collect::<Vec<String>>();
+        let flags = [
+            (self.modifiers.case_insensitive, "-i"),
+            (self.modifiers.whole_word, "-w"),
+        ];
+        for (active, flag) in flags {
+            if active {
+                words.push(flag.to_string());
+            }
+        }
+        words.join(" ")
+    }
+}
+
+/// Joins the glob, search and replace tokens into a compact line, up to the
+/// first missing one.
+pub fn compact_line(tokens: &[Option<String>]) -> String {
+    tokens
+        .iter()
+        .map_while(|token| token.as_deref())
+        .map(quote)
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Quotes `token` so that it reads back as is, never as a modifier or a
+/// regular expression.
+fn quote(token: &str) -> String {
+    let escaped = token
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    let plain = !token.is_empty()
+        && !token.contains([' ', '\'', '"'])
+        && !token.starts_with(['-', '!', '/'])
+        && !token.starts_with("t:");
+    match plain {
+        true => escaped,
+        false => format!("\"{}\"", escaped),
+    }
+}
+
+/// Writes `pattern` as a `/regex/` token, escaping the characters that
+/// would end it.
+fn regex_token(pattern: &str) -> String {
+    let escaped = ['/', ' ', '"', '\'']
+        .iter()
+        .fold(pattern.to_string(), |pattern, char| {
+            pattern.replace(*char, &format!("\\{}", char))
+        });
+    format!("/{}/", escaped)
+}
+
+#[test]
+fn form_fields_are_literal() {
+    let mut prompt = Prompt::new("src/* fn", false);
+    assert_eq!(prompt.editor().text(), "fn");
+    prompt.editor_mut().insert(" \"x\"");
+    assert_eq!(prompt.split().search, Some("fn \"x\"".to_string()));
+    assert_eq!(prompt.split().replace, None);
+
+    assert!(prompt.cycle_focus(false));
+    assert_eq!(prompt.focus, Field::Replace);
+    assert_eq!(prompt.split().replace, Some(String::new()));
+    assert!(prompt.cycle_focus(false));
+    assert_eq!(prompt.focus, Field::Glob);
+    assert_eq!(prompt.split().replace, Some(String::new()));
+    assert_eq!(prompt.query(), r#"src/* "fn \"x\"" """#);
+    assert!(prompt.cycle_focus(true));
+    assert_eq!(prompt.focus, Field::Replace);
+
+    prompt.toggle_compact();
+    assert_eq!(prompt.split().replace, Some(String::new()));
+    prompt.toggle_compact();
+    assert!(prompt.replacing);
+    assert!(!Prompt::new("src/* fn", false).replacing);
+}
+
+#[test]
+fn toggle_form_modes() {
+    let mut prompt = Prompt::new(r"src/* a/b\s+ c", false);
+    assert!(prompt.toggle_mode(|modifiers| &mut modifiers.regex));
+    assert!(prompt.toggle_mode(|modifiers| &mut modifiers.case_insensitive));
+    let split = prompt.split();
+    assert!(split.modifiers.regex && split.modifiers.case_insensitive);
+    assert!(!split.modifiers.whole_word);
+    assert_eq!(split.search.as_deref(), Some(r"a/b\s+"));
+    assert_eq!(prompt.query(), r"src/* /a\/b\s+/ c -i");
+
+    prompt.toggle_compact();
+    assert_eq!(prompt.split().search.as_deref(), Some(r"a/b\s+"));
+    assert!(prompt.split().modifiers.case_insensitive);
+    assert!(!prompt.toggle_mode(|modifiers| &mut modifiers.whole_word));
+    prompt.toggle_compact();
+    assert_eq!(prompt.modifiers, split.modifiers);
+    assert!(prompt.toggle_mode(|modifiers| &mut modifiers.regex));
+    assert_eq!(prompt.query(), r"src/* a/b\\s+ c -i");
+}
+
+#[test]
+fn toggle_compact_mode() {
+    let mut prompt = Prompt::new("src/* fn", false);
+    prompt.editor_mut().insert(" main");
+    prompt.toggle_compact();
+    assert!(prompt.compact);
+    assert_eq!(prompt.editor().text(), "src/* \"fn main\"");
+    assert!(!prompt.cycle_focus(false));
+
+    prompt.editor_mut().insert(r#" 'say "hi"\t\\'"#);
+    prompt.toggle_compact();
+    assert_eq!(prompt.field(Field::Replace).text(), "say \"hi\"\t\\");
+
+    prompt.fields[0] = LineEditor::new("-i");
+    prompt.toggle_compact();
+    assert_eq!(prompt.editor().text(), r#""-i" "fn main" "say \"hi\"\t\\""#);
+    assert_eq!(prompt.split().glob, Some("-i".to_string()));
+    assert_eq!(prompt.split().search, Some("fn main".to_string()));
+}
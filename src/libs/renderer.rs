@@ -0,0 +1,566 @@
+use std::fmt;
+use std::io::{stdout, Error, Write};
+
+use crossterm::cursor::MoveTo;
+use crossterm::queue;
+use crossterm::style::{Color, Print};
+use crossterm::terminal::{BeginSynchronizedUpdate, Clear, ClearType, EndSynchronizedUpdate};
+use unicode_width::UnicodeWidthChar;
+
+use super::terminal::ScreenSize;
+use super::theme::{ansi_16_sgr, ColorDepth};
+
+const RESET: &str = "\x1b[0m";
+/// Columns between tab stops, counted from where the text is printed.
+const TAB_WIDTH: usize = 4;
+/// Symbol of the cell covered by the right half of a wide character.
+const CONTINUATION: char = '\0';
+
+/// Drawing surface of the interactive mode.
+pub trait Renderer {
+    /// Prints `text` from column `x` of row `y`. The text may contain ANSI
+    /// style sequences.
+    fn print_at(&mut self, x: u16, y: u16, text: &str) -> Result<(), Error>;
+
+    fn clear_line(&mut self, y: u16) -> Result<(), Error>;
+
+    fn clear_all(&mut self) -> Result<(), Error>;
+
+    fn clear_lines(&mut self, lines: &[u16]) -> Result<(), Error> {
+        for y in lines {
+            self.clear_line(*y)?;
+        }
+        Ok(())
+    }
+
+    /// Shows what was drawn since the last flush.
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Renders to the terminal through crossterm. Frames are drawn in memory and
+/// each flush only sends the cells that changed since the previous frame,
+/// in a single write wrapped in a synchronized update. Terminals without
+/// synchronized updates ignore those sequences.
+pub struct CrosstermRenderer {
+    front: BufferRenderer,
+    back: BufferRenderer,
+    cleared: bool,
+    colored: bool,
+    depth: ColorDepth,
+}
+
+impl CrosstermRenderer {
+    /// Expects a blank screen of size `screen`.
+    pub fn new(screen: ScreenSize) -> CrosstermRenderer {
+        CrosstermRenderer {
+            front: BufferRenderer::new(screen),
+            back: BufferRenderer::new(screen),
+            cleared: false,
+            colored: true,
+            depth: ColorDepth::default(),
+        }
+    }
+
+    /// Draws without colours when `colored` is false, keeping the other
+    /// attributes such as bold or reverse.
+    pub fn colored(mut self, colored: bool) -> CrosstermRenderer {
+        self.colored = colored;
+        self
+    }
+
+    /// Writes colours as a terminal of `depth` reads them.
+    pub fn depth(mut self, depth: ColorDepth) -> CrosstermRenderer {
+        self.depth = depth;
+        self
+    }
+
+    /// Starts over from a blank screen, the terminal content being unknown
+    /// after a resize.
+    pub fn resize(&mut self, screen: ScreenSize) {
+        *self = CrosstermRenderer::new(screen)
+            .colored(self.colored)
+            .depth(self.depth);
+        self.cleared = true;
+    }
+}
+
+impl Renderer for CrosstermRenderer {
+    fn print_at(&mut self, x: u16, y: u16, text: &str) -> Result<(), Error> {
+        match (self.colored, self.depth) {
+            (false, _) => self.back.print_at(x, y, &strip_colors(text)),
+            (true, ColorDepth::Ansi16) => self.back.print_at(x, y, &basic_colors(text)),
+            (true, _) => self.back.print_at(x, y, text),
+        }
+    }
+
+    fn clear_line(&mut self, y: u16) -> Result<(), Error> {
+        self.back.clear_line(y)
+    }
+
+    fn clear_all(&mut self) -> Result<(), Error> {
+        self.back.clear_all()
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if !self.cleared && self.front.cells == self.back.cells {
+            return Ok(());
+        }
+        let mut out = stdout().lock();
+        queue!(out, BeginSynchronizedUpdate)?;
+        if self.cleared {
+            queue!(out, Clear(ClearType::All))?;
+            self.cleared = false;
+        }
+        draw_changes(&mut out, &self.front, &self.back)?;
+        queue!(out, EndSynchronizedUpdate)?;
+        out.flush()?;
+        self.front.cells.clone_from(&self.back.cells);
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Cell {
+    /// The character in the cell, or [`CONTINUATION`] right of a wide one.
+    symbol: char,
+    /// SGR sequences in effect, empty for the default style.
+    style: String,
+}
+
+impl Default for Cell {
+    fn default() -> Cell {
+        Cell {
+            symbol: ' ',
+            style: String::new(),
+        }
+    }
+}
+
+/// In-memory grid of cells, also used for tests of complete screens. Text
+/// past the right edge is clipped. Wide characters take two cells, tabs are
+/// expanded to spaces and characters without width are left out.
+#[derive(Clone)]
+pub struct BufferRenderer {
+    screen: ScreenSize,
+    cells: Vec<Cell>,
+}
+
+impl BufferRenderer {
+    pub fn new(screen: ScreenSize) -> BufferRenderer {
+        BufferRenderer {
+            screen,
+            cells: vec![Cell::default(); screen.width * screen.height],
+        }
+    }
+
+    /// Rows of the screen without styles or trailing spaces.
+    pub fn lines(&self) -> Vec<String> {
+        self.cells
+            .chunks(self.screen.width.max(1))
+            .map(|row| {
+                row.iter()
+                    .map(|cell| cell.symbol)
+                    .filter(|symbol| *symbol != CONTINUATION)
+                    .collect::<String>()
+                    .trim_end()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    fn set(&mut self, x: usize, y: usize, symbol: char, style: &str) {
+        if x >= self.screen.width || y >= self.screen.height {
+            return;
+        }
+        let i = y * self.screen.width + x;
+        // Overwriting half of a wide character blanks the other half.
+        if symbol != CONTINUATION && x > 0 && self.cells[i].symbol == CONTINUATION {
+            self.cells[i - 1] = Cell::default();
+        }
+        if x + 1 < self.screen.width && self.cells[i + 1].symbol == CONTINUATION {
+            self.cells[i + 1] = Cell::default();
+        }
+        let cell = &mut self.cells[i];
+        cell.symbol = symbol;
+        cell.style.clear();
+        cell.style.push_str(style);
+    }
+}
+
+impl Renderer for BufferRenderer {
+    fn print_at(&mut self, x: u16, y: u16, text: &str) -> Result<(), Error> {
+        let (mut x, mut y) = (x as usize, y as usize);
+        let mut start = x;
+        let mut style = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\x1b' => apply_escape_sequence(&mut chars, &mut style),
+                // Raw mode: a line feed moves down without going back to the
+                // first column.
+                '\n' => y += 1,
+                '\r' => (x, start) = (0, 0),
+                '\t' => {
+                    let stop = x + TAB_WIDTH - (x - start) % TAB_WIDTH;
+                    while x < stop {
+                        self.set(x, y, ' ', &style);
+                        x += 1;
+                    }
+                }
+                c => match c.width().unwrap_or(0) {
+                    0 => (),
+                    // Clipped, as it does not fit on the last column.
+                    2 if x + 1 == self.screen.width => {
+                        self.set(x, y, ' ', &style);
+                        x += 1;
+                    }
+                    width => {
+                        self.set(x, y, c, &style);
+                        if width == 2 {
+                            self.set(x + 1, y, CONTINUATION, &style);
+                        }
+                        x += width;
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn clear_line(&mut self, y: u16) -> Result<(), Error> {
+        for x in 0..self.screen.width {
+            self.set(x, y as usize, ' ', "");
+        }
+        Ok(())
+    }
+
+    fn clear_all(&mut self) -> Result<(), Error> {
+        self.cells.fill(Cell::default());
+        Ok(())
+    }
+}
+
+impl fmt::Display for BufferRenderer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.lines().join("\n"))
+    }
+}
+
+/// Reads an escape sequence, keeping track of the SGR (style) sequences in
+/// `style`. Other CSI sequences, and other escapes, are dropped.
+fn apply_escape_sequence(chars: &mut std::str::Chars, style: &mut String) {
+    if chars.next() != Some('[') {
+        return;
+    }
+    let mut parameters = String::new();
+    for c in chars.by_ref() {
+        if !('\x40'..='\x7e').contains(&c) {
+            parameters.push(c);
+            continue;
+        }
+        if c == 'm' {
+            match parameters.as_str() {
+                "" | "0" => style.clear(),
+                _ => style.push_str(&format!("\x1b[{}m", parameters)),
+            }
+        }
+        return;
+    }
+}
+
+/// Removes the style sequences of `text`.
+pub fn strip_styles(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut style = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => apply_escape_sequence(&mut chars, &mut style),
+            c => plain.push(c),
+        }
+    }
+    plain
+}
+
+/// The part of `text` that fits in `width` columns, as drawn by
+/// [`BufferRenderer`], with the style sequences among them.
+pub fn clip(text: &str, width: usize) -> String {
+    let mut clipped = String::with_capacity(text.len());
+    let mut columns = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                clipped.push(c);
+                if chars.next().inspect(|c| clipped.push(*c)) != Some('[') {
+                    continue;
+                }
+                for c in chars.by_ref() {
+                    clipped.push(c);
+                    if ('\x40'..='\x7e').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            c => {
+                let next = match c {
+                    '\t' => columns + TAB_WIDTH - columns % TAB_WIDTH,
+                    c => columns + c.width().unwrap_or(0),
+                };
+                if next > width || columns == width {
+                    break;
+                }
+                clipped.push(c);
+                columns = next;
+            }
+        }
+    }
+    clipped
+}
+
+/// Removes the colours of the style sequences of `text`, keeping the other
+/// attributes. Sequences left without parameters are dropped.
+pub fn strip_colors(text: &str) -> String {
+    map_colors(text, |_, _| None)
+}
+
+/// Writes the colours of the style sequences of `text` with the SGR codes
+/// of 16-colour terminals, 30–37 and 90–97 or 40–47 and 100–107 for
+/// backgrounds, in place of the 256-colour codes crossterm writes even for
+/// named colours.
+pub fn basic_colors(text: &str) -> String {
+    map_colors(text, |color, background| {
+        Some(ansi_16_sgr(color, background).to_string())
+    })
+}
+
+/// Rewrites each colour of the style sequences of `text` with the
+/// parameters `color` returns for it and whether it is a background, or
+/// leaves it out for `None`. The colours of the 16 are given by number and
+/// the default colours as [`Color::Reset`]. Underline colours are left out,
+/// and sequences left without parameters are dropped.
+fn map_colors(text: &str, mut color: impl FnMut(Color, bool) -> Option<String>) -> String {
+    let mut mapped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("\x1b[") {
+        mapped.push_str(&rest[..start]);
+        let sequence = &rest[start + 2..];
+        let end = match sequence.find(|c| ('\x40'..='\x7e').contains(&c)) {
+            Some(end) => end,
+            None => {
+                rest = "";
+                break;
+            }
+        };
+        rest = &sequence[end + 1..];
+        let parameters = &sequence[..end];
+        if !sequence[end..].starts_with('m') || matches!(parameters, "" | "0") {
+            mapped.push_str(&format!("\x1b[{}", &sequence[..=end]));
+            continue;
+        }
+        let mut kept = vec![];
+        let mut parameters = parameters.split(';');
+        while let Some(parameter) = parameters.next() {
+            let mut number = || parameters.next().and_then(|n| n.parse::<u8>().ok());
+            let parsed = match parameter.parse::<u8>() {
+                Ok(code @ (38 | 48 | 58)) => {
+                    let extended = match number() {
+                        Some(5) => number().map(Color::AnsiValue),
+                        Some(2) => match (number(), number(), number()) {
+                            (Some(r), Some(g), Some(b)) => Some(Color::Rgb { r, g, b }),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    match code {
+                        58 => None,
+                        _ => extended.map(|extended| (extended, code == 48)),
+                    }
+                }
+                Ok(code @ (30..=37 | 40..=47)) => Some((Color::AnsiValue(code % 10), code >= 40)),
+                Ok(code @ (90..=97 | 100..=107)) => {
+                    Some((Color::AnsiValue(code % 10 + 8), code >= 100))
+                }
+                Ok(code @ (39 | 49)) => Some((Color::Reset, code == 49)),
+                _ => {
+                    kept.push(parameter.to_string());
+                    continue;
+                }
+            };
+            if let Some((parsed, background)) = parsed {
+                kept.extend(color(parsed, background));
+            }
+        }
+        if !kept.is_empty() {
+            mapped.push_str(&format!("\x1b[{}m", kept.join(";")));
+        }
+    }
+    mapped.push_str(rest);
+    mapped
+}
+
+/// Writes the cells of `back` that differ from `front`, moving the cursor
+/// only when the changed cells are not contiguous.
+fn draw_changes(
+    out: &mut impl Write,
+    front: &BufferRenderer,
+    back: &BufferRenderer,
+) -> Result<(), Error> {
+    let width = back.screen.width.max(1);
+    let mut cursor = None;
+    let mut style: Option<&str> = None;
+    for (i, (old, new)) in front.cells.iter().zip(&back.cells).enumerate() {
+        if old == new || new.symbol == CONTINUATION {
+            continue;
+        }
+        let (x, y) = (i % width, i / width);
+        if cursor != Some((x, y)) {
+            queue!(out, MoveTo(x as u16, y as u16))?;
+        }
+        if style != Some(new.style.as_str()) {
+            queue!(out, Print(RESET), Print(&new.style))?;
+            style = Some(&new.style);
+        }
+        queue!(out, Print(new.symbol))?;
+        cursor = Some((x + new.symbol.width().unwrap_or(1).max(1), y));
+    }
+    if style.is_some() {
+        queue!(out, Print(RESET))?;
+    }
+    Ok(())
+}
+
+#[test]
+fn buffer_keeps_styles_and_clips() {
+    use crossterm::style::Stylize;
+
+    let mut buffer = BufferRenderer::new(ScreenSize {
+        width: 6,
+        height: 2,
+    });
+    buffer
+        .print_at(1, 0, &format!("a{}c", "b".stylize().green().bold()))
+        .unwrap();
+    buffer.print_at(3, 1, "long text").unwrap();
+    assert_eq!(buffer.lines(), vec![" abc", "   lon"]);
+    assert_eq!(buffer.cells[1].style, "");
+    assert_eq!(buffer.cells[2].style, "\x1b[38;5;10m\x1b[1m");
+    assert_eq!(buffer.cells[3].style, "");
+    buffer.clear_line(1).unwrap();
+    assert_eq!(buffer.to_string(), " abc\n");
+}
+
+#[test]
+fn buffer_expands_tabs_and_wide_characters() {
+    let mut buffer = BufferRenderer::new(ScreenSize {
+        width: 10,
+        height: 2,
+    });
+    buffer.print_at(1, 0, "\ta\tb").unwrap();
+    buffer.print_at(0, 1, "中文ab").unwrap();
+    assert_eq!(buffer.lines(), vec!["     a   b", "中文ab"]);
+    let front = buffer.clone();
+
+    // Half a wide character overwritten blanks the other half.
+    buffer.print_at(1, 1, "x").unwrap();
+    buffer.print_at(8, 1, "表").unwrap();
+    buffer.print_at(9, 0, "表").unwrap();
+    assert_eq!(buffer.lines(), vec!["     a", " x文ab  表"]);
+    let mut written = vec![];
+    draw_changes(&mut written, &front, &buffer).unwrap();
+    assert_eq!(
+        String::from_utf8(written).unwrap(),
+        "\x1b[1;10H\x1b[0m \x1b[2;1H x\x1b[2;9H表\x1b[0m"
+    );
+
+    let mut written = vec![];
+    draw_changes(&mut written, &BufferRenderer::new(buffer.screen), &front).unwrap();
+    assert!(String::from_utf8(written).unwrap().contains("中文ab"));
+}
+
+#[test]
+fn clip_keeping_styles() {
+    use crossterm::style::Stylize;
+
+    let styled = format!("a{}cd", "b".green());
+    assert_eq!(clip(&styled, 2), format!("a{}", "b".green()));
+    assert_eq!(clip(&styled, 1), "a\x1b[38;5;10m");
+    assert_eq!(clip(&styled, 10), styled);
+    assert_eq!(clip("abc", 0), "");
+    assert_eq!(clip("中文ab", 3), "中");
+    assert_eq!(clip("\tab", 5), "\ta");
+}
+
+#[test]
+fn strip_colors_keeping_attributes() {
+    use crossterm::style::{Color, Stylize};
+
+    let styled = format!(
+        "a{}c{}",
+        "b".with(Color::Rgb { r: 1, g: 2, b: 3 })
+            .on(Color::AnsiValue(239))
+            .bold(),
+        "d".green().reverse()
+    );
+    assert_eq!(strip_colors(&styled), "a\x1b[1mb\x1b[0mc\x1b[7md\x1b[0m");
+    assert_eq!(
+        strip_colors("\x1b[1;31;48;2;1;2;3mx\x1b[2K"),
+        "\x1b[1mx\x1b[2K"
+    );
+    assert_eq!(strip_colors("plain \x1b["), "plain ");
+}
+
+#[test]
+fn write_basic_colors() {
+    use crossterm::style::Stylize;
+
+    let styled = format!(
+        "{}{}",
+        "a".with(Color::Red).on(Color::DarkBlue),
+        "b".with(Color::Rgb {
+            r: 0x88,
+            g: 0xc0,
+            b: 0xd0
+        })
+        .on(Color::AnsiValue(236))
+        .bold()
+    );
+    assert_eq!(
+        styled,
+        "\x1b[48;5;4m\x1b[38;5;9ma\x1b[49m\x1b[39m\x1b[48;5;236m\x1b[38;2;136;192;208m\x1b[1mb\x1b[0m"
+    );
+    assert_eq!(
+        basic_colors(&styled),
+        "\x1b[44m\x1b[91ma\x1b[49m\x1b[39m\x1b[40m\x1b[37m\x1b[1mb\x1b[0m"
+    );
+    assert_eq!(
+        basic_colors("\x1b[1;32;49mc\x1b[39m"),
+        "\x1b[1;32;49mc\x1b[39m"
+    );
+}
+
+#[test]
+fn draw_only_changed_cells() {
+    let screen = ScreenSize {
+        width: 8,
+        height: 2,
+    };
+    let mut front = BufferRenderer::new(screen);
+    front.print_at(0, 0, "abc").unwrap();
+    front.print_at(0, 1, "xyz").unwrap();
+    let mut back = front.clone();
+
+    let mut written = vec![];
+    draw_changes(&mut written, &front, &back).unwrap();
+    assert!(written.is_empty());
+
+    back.print_at(0, 0, "abd").unwrap();
+    back.print_at(0, 1, "\x1b[1mxy\x1b[0mw").unwrap();
+    draw_changes(&mut written, &front, &back).unwrap();
+    assert_eq!(
+        String::from_utf8(written).unwrap(),
+        "\x1b[1;3H\x1b[0md\x1b[2;1H\x1b[0m\x1b[1mxy\x1b[0mw\x1b[0m"
+    );
+}
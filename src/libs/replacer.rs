@@ -0,0 +1,397 @@
+use std::io::{Error, ErrorKind};
+use std::ops::Range;
+use std::path::Path;
+
+use super::decorate_file_content::{happend_changes_in_file, side_by_side, Preview};
+use super::file_store::list_files;
+use super::line_endings::{replace_preserving_endings, replace_ranges_preserving_endings};
+use super::matcher::Matcher;
+use super::search::SearchOptions;
+use super::syntax_highlight::SyntaxHighlighter;
+#[cfg(test)]
+use super::temp_dir::TempDir;
+
+/// Files a [`SearchPlan`] is generated for.
+#[derive(Debug, PartialEq, Clone)]
+pub enum FileSource {
+    /// Files matching a glob pattern such as `src/**/*.rs`.
+    Glob(String),
+    /// An explicit list of files.
+    Paths(Vec<String>),
+}
+
+impl FileSource {
+    /// Lists the files of the source, ignoring directories.
+    pub fn files(&self) -> Result<Vec<String>, Error> {
+        match self {
+            FileSource::Glob(pattern) => Ok(list_files(pattern)?
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect()),
+            FileSource::Paths(paths) => Ok(paths
+                .iter()
+                .filter(|path| Path::new(path).is_file())
+                .cloned()
+                .collect()),
+        }
+    }
+}
+
+/// Changes planned for a single file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FilePlan {
+    pub path: String,
+    pub changes: usize,
+    /// Changed lines with the substitute applied, styled for a terminal.
+    pub preview: Vec<String>,
+}
+
+/// Files that would change, computed without writing anything.
+#[derive(Debug, PartialEq, Default, Clone)]
+pub struct SearchPlan {
+    pub files: Vec<FilePlan>,
+    /// Files left out for being larger than the maximum file size.
+    pub skipped: Vec<String>,
+}
+
+impl SearchPlan {
+    pub fn replaced_count(&self) -> usize {
+        self.files.iter().map(|file| file.changes).sum()
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+}
+
+/// Search and replace engine shared by the interactive and classic modes.
+///
+/// ```
+/// use rplc::Replacer;
+///
+/// let replacer = Replacer::new("colour", "color");
+/// assert_eq!(replacer.replace("colour, colours"), ("color, colors".to_string(), 2));
+///
+/// let replacer = Replacer::new("a\nb", "c").eol_insensitive(true);
+/// assert_eq!(replacer.replace("a\r\nb"), ("c".to_string(), 1));
+/// ```
+pub struct Replacer {
+    query: String,
+    substitute: String,
+    options: SearchOptions,
+    matcher: Matcher,
+    /// Why the query is not a valid regular expression.
+    invalid: Option<regex::Error>,
+}
+
+impl Replacer {
+    pub fn new(query: &str, substitute: &str) -> Replacer {
+        Replacer {
+            query: query.to_string(),
+            substitute: substitute.to_string(),
+            options: SearchOptions::default(),
+            matcher: Matcher::new(query, false),
+            invalid: None,
+        }
+    }
+
+    /// Matches line breaks of the query against both `\n` and `\r\n`.
+    pub fn eol_insensitive(self, eol_insensitive: bool) -> Replacer {
+        let options = SearchOptions {
+            eol_insensitive,
+            ..self.options.clone()
+        };
+        self.options(options)
+    }
+
+    /// Leaves files larger than `max_filesize` bytes out of plans.
+    pub fn max_filesize(mut self, max_filesize: u64) -> Replacer {
+        self.options.max_filesize = max_filesize;
+        self
+    }
+
+    /// An invalid regular expression matches nothing, see
+    /// [`Replacer::validate`].
+    pub fn options(mut self, options: SearchOptions) -> Replacer {
+        (self.matcher, self.invalid) = match Matcher::with_options(&self.query, &options) {
+            Ok(matcher) => (matcher, None),
+            Err(e) => (Matcher::new("", false), Some(e)),
+        };
+        self.options = options;
+        self
+    }
+
+    /// Fails when the query is not a valid regular expression.
+    ///
+    /// ```
+    /// use rplc::{Replacer, SearchOptions};
+    ///
+    /// let options = SearchOptions {
+    ///     regex: true,
+    ///     ..SearchOptions::default()
+    /// };
+    /// let replacer = Replacer::new("(a", "b").options(options);
+    /// let e = replacer.validate().unwrap_err();
+    /// assert_eq!(e.to_string(), "invalid regular expression: unclosed group");
+    /// ```
+    pub fn validate(&self) -> Result<(), Error> {
+        match &self.invalid {
+            // Syntax errors point at the query over several lines: only
+            // their last line fits in the status bar.
+            Some(e) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "invalid regular expression: {}",
+                    e.to_string()
+                        .lines()
+                        .last()
+                        .unwrap_or_default()
+                        .trim_start_matches("error: ")
+                ),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn substitute(&self) -> &str {
+        &self.substitute
+    }
+
+    /// Byte ranges of the non-overlapping matches of the query.
+    pub fn find(&self, content: &str) -> Vec<Range<usize>> {
+        self.matcher.find_all(content)
+    }
+
+    /// Returns `content` with every match replaced, and the number of
+    /// replacements. Line endings of the content are preserved.
+    pub fn replace(&self, content: &str) -> (String, usize) {
+        replace_preserving_endings(content, &self.matcher, &self.substitute)
+    }
+
+    /// Like [`Replacer::replace`], leaving the matches for which `skip` is
+    /// true. Matches are numbered from 0 in the order they are found.
+    ///
+    /// ```
+    /// use rplc::Replacer;
+    ///
+    /// let replacer = Replacer::new("a", "b");
+    /// let (content, changes) = replacer.replace_matches("a\naa", |i| i == 1);
+    /// assert_eq!((content.as_str(), changes), ("b\nab", 2));
+    /// ```
+    pub fn replace_matches(&self, content: &str, skip: impl Fn(usize) -> bool) -> (String, usize) {
+        let matches = self
+            .find(content)
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !skip(*i))
+            .map(|(_, range)| range)
+            .collect::<Vec<Range<usize>>>();
+        let replaced =
+            replace_ranges_preserving_endings(content, &matches, &self.matcher, &self.substitute);
+        (replaced, matches.len())
+    }
+
+    /// Renders the changed lines of `content` with the text replacement
+    /// removes and adds, in the diff style of the options, with line numbers
+    /// and `...` between distant changes.
+    ///
+    /// ```
+    /// use rplc::Replacer;
+    ///
+    /// let (lines, changes) = Replacer::new("b", "c").preview("a\nb\n");
+    /// assert_eq!(changes, 1);
+    /// assert_eq!(lines.len(), 2);
+    /// ```
+    pub fn preview(&self, content: &str) -> (Vec<String>, usize) {
+        let (preview, changes) = self.render(content, true, None);
+        (preview.lines, changes)
+    }
+
+    /// Renders the lines of `content` with the matches highlighted as found,
+    /// which differs from the query for regular expressions or when case is
+    /// ignored.
+    pub fn highlight(&self, content: &str) -> (Vec<String>, usize) {
+        let (preview, matches) = self.render(content, false, None);
+        (preview.lines, matches)
+    }
+
+    /// Highlighter of the file at `path` in the theme and colour depth of the
+    /// options, `None` without a theme or for plain text.
+    pub fn syntax(&self, path: &str, content: &str) -> Option<SyntaxHighlighter> {
+        let theme = self.options.theme.as_deref()?;
+        let syntax = SyntaxHighlighter::for_file(path, content, theme)?;
+        Some(syntax.depth(self.options.color_depth))
+    }
+
+    /// Renders `content` as [`preview`](Replacer::preview) does when
+    /// `replacing`, else as [`highlight`](Replacer::highlight), coloured by
+    /// `syntax` when given, the context lines told apart.
+    pub(crate) fn render(
+        &self,
+        content: &str,
+        replacing: bool,
+        syntax: Option<&SyntaxHighlighter>,
+    ) -> (Preview, usize) {
+        let substitute = replacing.then_some(self.substitute.as_str());
+        happend_changes_in_file(
+            content,
+            &self.matcher,
+            substitute,
+            self.options.context,
+            syntax,
+            self.options.diff,
+        )
+    }
+
+    /// Renders the whole of `content` as it is next to how it would be when
+    /// `replacing`, else with the matches highlighted, as rows of both sides.
+    ///
+    /// ```
+    /// use rplc::Replacer;
+    ///
+    /// let rows = Replacer::new("b", "c").side_by_side("a\nb\n", true, None);
+    /// assert_eq!(rows.len(), 2);
+    /// assert!(rows[1].1.contains('c'));
+    /// ```
+    pub fn side_by_side(
+        &self,
+        content: &str,
+        replacing: bool,
+        syntax: Option<&SyntaxHighlighter>,
+    ) -> Vec<(String, String)> {
+        let substitute = replacing.then_some(self.substitute.as_str());
+        side_by_side(content, &self.matcher, substitute, syntax)
+    }
+
+    /// Searches the files of `source` and plans the changes, without writing.
+    /// Files that cannot be read as UTF-8 text are ignored.
+    ///
+    /// ```
+    /// use rplc::{FileSource, Replacer};
+    /// # mod temp_dir {
+    /// #     include!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/libs/temp_dir.rs"));
+    /// # }
+    ///
+    /// # let dir = temp_dir::TempDir::new("plan-doctest");
+    /// let path = dir.file("plan.txt");
+    /// std::fs::write(&path, "hello world\n").unwrap();
+    ///
+    /// let replacer = Replacer::new("world", "there");
+    /// let plan = replacer.plan(&FileSource::Paths(vec![path.clone()])).unwrap();
+    /// assert_eq!(plan.replaced_count(), 1);
+    /// assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello world\n");
+    ///
+    /// assert_eq!(replacer.write(&plan).unwrap(), 1);
+    /// assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello there\n");
+    /// ```
+    pub fn plan(&self, source: &FileSource) -> Result<SearchPlan, Error> {
+        let mut plan = SearchPlan::default();
+        for path in source.files()? {
+            if !self.options.includes(&path) {
+                continue;
+            }
+            let size = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+            if self.options.exceeds_max_filesize(size) {
+                plan.skipped.push(path);
+                continue;
+            }
+            let content = match std::fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(_e) => continue,
+            };
+            let (preview, changes) = self.preview(&content);
+            if changes > 0 {
+                plan.files.push(FilePlan {
+                    path,
+                    changes,
+                    preview,
+                });
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Applies the replacements to the files of `plan`. Files are read again,
+    /// so changes made since the plan was generated are taken into account.
+    /// Returns the number of replacements made.
+    pub fn write(&self, plan: &SearchPlan) -> Result<usize, Error> {
+        let mut replaced = 0;
+        for file in &plan.files {
+            replaced += self.write_file(&file.path)?;
+        }
+        Ok(replaced)
+    }
+
+    pub fn write_file(&self, path: &str) -> Result<usize, Error> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(_e) => return Ok(0),
+        };
+        let (new_content, changes) = self.replace(&content);
+        if changes > 0 {
+            std::fs::write(path, new_content)?;
+        }
+        Ok(changes)
+    }
+}
+
+#[test]
+fn plan_skips_large_files() {
+    let dir = TempDir::new("plan-skips-large-files");
+    let small = dir.join("small.txt");
+    let large = dir.join("large.txt");
+    std::fs::write(&small, "abc").unwrap();
+    std::fs::write(&large, "abc".repeat(10)).unwrap();
+
+    let replacer = Replacer::new("b", "x").max_filesize(10);
+    let pattern = format!("{}/*.txt", dir.to_str().unwrap());
+    let plan = replacer.plan(&FileSource::Glob(pattern)).unwrap();
+    assert_eq!(plan.file_count(), 1);
+    assert_eq!(plan.files[0].path, small.to_str().unwrap());
+    assert_eq!(plan.skipped, vec![large.to_str().unwrap().to_string()]);
+}
+
+#[test]
+fn insert_at_empty_matches() {
+    let regex = SearchOptions {
+        regex: true,
+        ..SearchOptions::default()
+    };
+    let comment = Replacer::new("^", "// ").options(regex.clone());
+    assert_eq!(
+        comment.replace("a\r\n\nb\n"),
+        ("// a\r\n// \n// b\n".to_string(), 3)
+    );
+    let (lines, _) = comment.replace_matches("a\nb", |i| i == 0);
+    assert_eq!(lines, "a\n// b");
+    let (preview, changes) = comment.preview("a\nb\n");
+    assert_eq!(changes, 2);
+    let preview = super::renderer::strip_styles(&preview.join("\n"));
+    assert!(preview.contains("1 // a") && preview.contains("2 // b"));
+    let rows = comment.side_by_side("a\nb\n", true, None);
+    assert!(super::renderer::strip_styles(&rows[1].1).ends_with("// b"));
+    let terminate = Replacer::new("$", ";").options(regex);
+    assert_eq!(terminate.replace("a\r\nb").0, "a;\r\nb;");
+}
+
+#[test]
+fn replace_with_captures() {
+    let options = SearchOptions {
+        regex: true,
+        eol_insensitive: true,
+        ..SearchOptions::default()
+    };
+    let replacer = Replacer::new(r"(\w+) = (\w+);", "let $2 =\n$1;").options(options);
+    let (content, changes) = replacer.replace("x = y;\r\nz = 1;\r\n");
+    assert_eq!(changes, 2);
+    assert_eq!(content, "let y =\r\nx;\r\nlet 1 =\r\nz;\r\n");
+    let (lines, _) = replacer.replace_matches("a = b;\r\n", |_| false);
+    assert_eq!(lines, "let b =\r\na;\r\n");
+    let (preview, _) = replacer.preview("x = y;\n");
+    let preview = super::renderer::strip_styles(&preview.join("\n"));
+    assert!(preview.contains("let y ="));
+}
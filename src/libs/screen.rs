@@ -0,0 +1,857 @@
+use std::io::Error;
+use std::time::Duration;
+
+use crossterm::style::{Color, Stylize};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use super::file_store::format_size;
+use super::keymap::Action;
+use super::line_editor::LineEditor;
+use super::prompt::Field;
+use super::renderer::{clip, strip_styles, Renderer};
+use super::scrollbar::display_scrollbar;
+use super::split_query::{split_query, token_spans, TokenKind};
+use super::state::{AppState, RESULTS_TOP};
+use super::syntax_highlight::ThemePicker;
+use super::terminal::clear_results;
+
+/// Columns taken by the labels of the form fields.
+const FIELD_LABEL_WIDTH: usize = 10;
+/// Separator of the parts of the status bar.
+const STATUS_SEPARATOR: &str = " · ";
+
+/// Prints the keys bound to the main actions on the first row, after the
+/// format of the query in compact mode.
+pub fn print_help(out: &mut dyn Renderer, state: &AppState) -> Result<(), Error> {
+    let palette = &state.palette;
+    let compact = state.prompt.compact;
+    let mut parts = vec![];
+    if compact {
+        parts.push(format!(
+            "{} {} {}",
+            "<glob>".with(palette.glob).bold(),
+            "<query>".with(palette.search).bold(),
+            "<replacement>".with(palette.replace).bold()
+        ));
+    }
+    let layout = if compact { "form" } else { "compact" };
+    let actions = [
+        (Action::Write, "write"),
+        (Action::Undo, "undo"),
+        (Action::NextField, "next field"),
+        (Action::ToggleCompact, layout),
+        (Action::NextFile, "next file"),
+        (Action::Quit, "exit"),
+    ];
+    for (action, name) in actions {
+        if compact && action == Action::NextField {
+            continue;
+        }
+        if let Some(key) = state.keymap.label(action) {
+            parts.push(format!("{} {}", key.bold(), name.with(palette.muted)));
+        }
+    }
+    let separator = STATUS_SEPARATOR.with(palette.muted).to_string();
+    out.print_at(0, 0, &parts.join(&separator))
+}
+
+/// Columns taken by `text` on the terminal, styles left out.
+fn display_width(text: &str) -> usize {
+    strip_styles(text).width()
+}
+
+/// Draws a box over the results listing every action with its keys, in as
+/// many columns as fit the screen. Rows that do not fit are scrolled.
+fn render_help(out: &mut dyn Renderer, state: &AppState) -> Result<(), Error> {
+    let entries = state.keymap.help_entries(state.options.diff);
+    let keys_width = entries.iter().map(|(keys, _)| keys.width()).max();
+    let keys_width = keys_width.unwrap_or(0);
+    let rows = state.help_rows();
+    let visible = state.view_height().saturating_sub(2);
+    let scroll = state.help_scroll.min(rows.saturating_sub(visible));
+
+    let mut lines = vec![String::new(); rows];
+    for (i, (keys, description)) in entries.iter().enumerate() {
+        let line = &mut lines[i % rows];
+        if i >= rows {
+            line.push_str("  ");
+        }
+        let padding = keys_width - keys.width() + 2;
+        let entry = format!(
+            "{}{}{}",
+            keys.clone().bold(),
+            " ".repeat(padding),
+            description
+        );
+        line.push_str(&entry);
+        if i + rows < entries.len() {
+            let column = i / rows * rows;
+            let entry_width = entries[column..(column + rows).min(entries.len())]
+                .iter()
+                .map(|(_, description)| keys_width + 2 + description.width())
+                .max();
+            let width = display_width(&entry);
+            line.push_str(&" ".repeat(entry_width.unwrap_or(width) - width));
+        }
+    }
+    render_box(out, state, " keys ", &lines, scroll)
+}
+
+/// Lists the themes over the results, the one selected highlighted and kept
+/// in view.
+fn render_theme_picker(
+    out: &mut dyn Renderer,
+    state: &AppState,
+    picker: &ThemePicker,
+) -> Result<(), Error> {
+    let lines: Vec<String> = picker
+        .names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| match i == picker.selected {
+            true => format!("▸ {}", name).bold().reverse().to_string(),
+            false => format!("  {}", name),
+        })
+        .collect();
+    let visible = state.view_height().saturating_sub(2).max(1);
+    let scroll = picker.selected.saturating_sub(visible - 1);
+    render_box(out, state, " theme ", &lines, scroll)
+}
+
+/// Draws a box titled `title` centred over the results with the `lines`
+/// that fit from `scroll`, and the last line shown out of all at the bottom
+/// when they do not all fit.
+fn render_box(
+    out: &mut dyn Renderer,
+    state: &AppState,
+    title: &str,
+    lines: &[String],
+    scroll: usize,
+) -> Result<(), Error> {
+    let rows = lines.len();
+    let visible = state.view_height().saturating_sub(2);
+    let inner = lines
+        .iter()
+        .map(|line| display_width(line))
+        .max()
+        .unwrap_or(0)
+        .max(title.width())
+        .min(state.screen.width.saturating_sub(4));
+    let left = (state.screen.width.saturating_sub(inner + 4) / 2) as u16;
+    let border = |title: &str| "─".repeat((inner + 1).saturating_sub(title.width()));
+    out.print_at(
+        left,
+        RESULTS_TOP as u16,
+        &format!("╭─{}{}╮", title, border(title)),
+    )?;
+    for (row, line) in lines.iter().skip(scroll).take(visible).enumerate() {
+        let y = (RESULTS_TOP + 1 + row) as u16;
+        let padding = inner.saturating_sub(display_width(line));
+        out.print_at(left, y, &format!("│ {}{}", line, " ".repeat(padding)))?;
+        out.print_at(left + inner as u16 + 2, y, " │")?;
+    }
+    let more = match rows > visible {
+        true => format!(" {}/{} ", (scroll + visible).min(rows), rows),
+        false => String::new(),
+    };
+    let bottom = RESULTS_TOP + 1 + rows.min(visible);
+    out.print_at(
+        left,
+        bottom as u16,
+        &format!("╰─{}{}╯", more, border(&more)),
+    )
+}
+
+/// Bottom line: counts of the results, search modes, progress or speed of
+/// the search and the last error, with the key of the help on the right.
+/// Trailing parts are left out when the line does not fit.
+pub fn print_status(out: &mut dyn Renderer, state: &AppState) -> Result<(), Error> {
+    let results = &state.results;
+    let palette = &state.palette;
+    let files_count = state.files.files_names().len();
+    let mut parts = vec![];
+    if let Some(error) = &state.last_error {
+        parts.push(format!("error: {}", error).with(palette.error).to_string());
+    }
+    parts.push(match (results.searched, results.replacing) {
+        (true, true) => format!(
+            "{} changes in {}/{} files",
+            results.replaced_count(),
+            results.file_count(),
+            files_count
+        ),
+        (true, false) => format!(
+            "{} matches in {}/{} files",
+            results.replaced_count(),
+            results.file_count(),
+            files_count
+        ),
+        _ => format!("{} files", files_count),
+    });
+    if state.files.skipped() > 0 {
+        let skipped = format!("{} skipped, too large or not text", state.files.skipped());
+        parts.push(skipped.with(palette.muted).to_string());
+    }
+    let options = state.split().modifiers.apply(&state.options);
+    for (active, mode) in [
+        (options.regex, "regex"),
+        (options.case_insensitive, "ignore case"),
+        (options.whole_word, "whole word"),
+        (options.eol_insensitive, "any line ending"),
+    ] {
+        if active {
+            parts.push(mode.with(palette.modifier).to_string());
+        }
+    }
+    let progress = &state.progress;
+    let elapsed = progress.started.elapsed().as_secs_f64();
+    parts.push(match (state.searching(), progress.files()) {
+        (true, 0) if !progress.cached => format!("listing files, {:.1} s", elapsed)
+            .with(palette.muted)
+            .to_string(),
+        (true, files) => format!(
+            "scanned {}/{} files, {:.1} s",
+            progress.scanned(),
+            files,
+            elapsed
+        )
+        .with(palette.muted)
+        .to_string(),
+        (false, _) => throughput(files_count, results.scanned_bytes(), results.elapsed)
+            .with(palette.muted)
+            .to_string(),
+    });
+    if progress.cached {
+        parts.push("cached listing".with(palette.muted).to_string());
+    }
+
+    let width = state.screen.width;
+    let hint = match state.keymap.label(Action::Help) {
+        Some(key) => format!("{} keys", key).with(palette.muted).to_string(),
+        None => String::new(),
+    };
+    let separator = STATUS_SEPARATOR.with(palette.muted).to_string();
+    let mut status = parts.join(&separator);
+    while parts.len() > 1 && display_width(&status) + display_width(&hint) + 1 > width {
+        parts.pop();
+        status = parts.join(&separator);
+    }
+    let y = state.screen.height.saturating_sub(1) as u16;
+    out.clear_line(y)?;
+    out.print_at(0, y, &status)?;
+    let hint_width = display_width(&hint);
+    if display_width(&status) + hint_width < width {
+        out.print_at((width - hint_width) as u16, y, &hint)?;
+    }
+    Ok(())
+}
+
+fn throughput(files: usize, bytes: u64, elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs_f64().max(0.001);
+    format!(
+        "{} files, {} in {} ms ({:.0} files/s, {}/s)",
+        files,
+        format_size(bytes),
+        elapsed.as_millis(),
+        files as f64 / seconds,
+        format_size((bytes as f64 / seconds) as u64)
+    )
+}
+
+/// Columns scrolled out on the left of an editor `width` columns wide.
+fn hidden_columns(editor: &LineEditor, width: usize, focused: bool) -> usize {
+    match focused {
+        true => editor.cursor_column().saturating_sub(width.max(1) - 1),
+        false => 0,
+    }
+}
+
+/// Renders `editor` in at most `width` columns, scrolled so that the cursor
+/// stays visible. `color` gives the colour of the grapheme at a byte offset.
+fn editor_line(
+    editor: &LineEditor,
+    width: usize,
+    focused: bool,
+    color: impl Fn(usize) -> Option<Color>,
+) -> String {
+    let text = editor.text();
+    let width = width.max(1);
+    let hidden = hidden_columns(editor, width, focused);
+
+    let mut line = String::new();
+    let mut column = 0;
+    let end = match focused && editor.cursor() == text.len() {
+        true => "█",
+        false => "",
+    };
+    let graphemes = text.grapheme_indices(true).chain([(text.len(), end)]);
+    for (i, grapheme) in graphemes {
+        let start = column;
+        column += grapheme.width();
+        if start < hidden {
+            continue;
+        }
+        if column - hidden > width {
+            break;
+        }
+        let mut styled = grapheme.stylize();
+        if let Some(color) = color(i) {
+            styled = styled.with(color).bold();
+        }
+        if focused && i == editor.cursor() && i < text.len() {
+            styled = styled.reverse();
+        }
+        line.push_str(&styled.to_string());
+    }
+    line
+}
+
+/// Draws the query with the cursor on the grapheme it is at: the fields of
+/// the form on rows 1 to 3, or the compact line, tokens highlighted, on row 2
+/// with the first parse error pointed at on row 3.
+pub fn print_prompt(out: &mut dyn Renderer, state: &AppState) -> Result<(), Error> {
+    let prompt = &state.prompt;
+    let palette = &state.palette;
+    let token_colors = [palette.glob, palette.search, palette.replace];
+    let width = state.screen.width;
+    out.clear_lines(&[1, 2, 3])?;
+    if let Some(search) = &state.history_search {
+        let label = "history search: ";
+        let term = editor_line(&search.term, width / 2, true, |_| Some(palette.search));
+        let found = match search.found {
+            Some(found) => state.history.entries()[found].query.clone().stylize(),
+            None => "no match".to_string().with(palette.muted),
+        };
+        return out.print_at(0, 2, &format!("{}{} {}", label.bold(), term, found));
+    }
+    if prompt.compact {
+        let text = prompt.line.text();
+        let spans = token_spans(text);
+        let errors = split_query(text).errors;
+        let line = editor_line(&prompt.line, width, true, |i| {
+            if errors.iter().any(|error| error.span.contains(&i)) {
+                return Some(palette.error);
+            }
+            let (_, kind) = spans.iter().find(|(span, _)| span.contains(&i))?;
+            Some(match kind {
+                TokenKind::Glob => palette.glob,
+                TokenKind::Search => palette.search,
+                TokenKind::Replace => palette.replace,
+                TokenKind::Modifier => palette.modifier,
+            })
+        });
+        out.print_at(0, 2, &line)?;
+        if let Some(error) = errors.first() {
+            let column = text[..error.span.start]
+                .width()
+                .saturating_sub(hidden_columns(&prompt.line, width, true));
+            let message = format!("^ {}", error.message).with(palette.error);
+            out.print_at(column as u16, 3, &message.to_string())?;
+        }
+        return Ok(());
+    }
+
+    for (i, field) in Field::ALL.iter().enumerate() {
+        let focused = *field == prompt.focus;
+        let label = format!("{: <8}│ ", field.label());
+        let label = match focused {
+            true => label.stylize().bold(),
+            false => label.stylize().with(palette.muted),
+        };
+        let line = editor_line(
+            prompt.field(*field),
+            width.saturating_sub(FIELD_LABEL_WIDTH),
+            focused,
+            |_| Some(token_colors[i]),
+        );
+        out.print_at(0, (i + 1) as u16, &format!("{}{}", label, line))?;
+    }
+    Ok(())
+}
+
+/// Clears the previous results while a search is running.
+pub fn render_loading(out: &mut dyn Renderer, state: &AppState) -> Result<(), Error> {
+    clear_results(out, state.screen)?;
+    out.print_at(0, 4, "Loading...")?;
+    render_overlay(out, state)?;
+    print_status(out, state)
+}
+
+/// Draws the theme picker or the help over the results, when shown.
+fn render_overlay(out: &mut dyn Renderer, state: &AppState) -> Result<(), Error> {
+    match &state.theme_picker {
+        Some(picker) => render_theme_picker(out, state, picker),
+        None if state.show_help => render_help(out, state),
+        None => Ok(()),
+    }
+}
+
+/// Redraws the whole screen for the current size, without searching again.
+pub fn render_screen(out: &mut dyn Renderer, state: &AppState) -> Result<(), Error> {
+    out.clear_all()?;
+    print_help(out, state)?;
+    print_prompt(out, state)?;
+    if state.searching() {
+        return render_loading(out, state);
+    }
+    render_results(out, state)
+}
+
+/// Draws the results of the current search at the current scroll offset,
+/// under the help when it is shown.
+pub fn render_results(out: &mut dyn Renderer, state: &AppState) -> Result<(), Error> {
+    clear_results(out, state.screen)?;
+    if let Some(notice) = &state.notice {
+        out.print_at(0, 4, &notice.clone().with(state.palette.notice).to_string())?;
+    }
+    let width = state.results_width();
+    for (i, line) in state.visible_lines().iter().enumerate() {
+        let text = clip(&line.text, width.saturating_sub(1));
+        out.print_at(0, (RESULTS_TOP + i) as u16, &text)?;
+    }
+    display_scrollbar(
+        out,
+        state.scroll_offset,
+        state.max_scroll() + 1,
+        RESULTS_TOP,
+        state.view_height(),
+        width.saturating_sub(1),
+    )?;
+    if state.split_layout() {
+        render_pane(out, state, width + 1)?;
+    }
+    render_overlay(out, state)?;
+    print_status(out, state)
+}
+
+/// Draws the selected file from column `left` to the right of the screen:
+/// as it is and as it would be side by side when replacing, else with the
+/// matches highlighted.
+fn render_pane(out: &mut dyn Renderer, state: &AppState, left: usize) -> Result<(), Error> {
+    let height = state.view_height();
+    let (path, rows) = match state.pane_view(height.saturating_sub(1)) {
+        Some(view) => view,
+        None => return Ok(()),
+    };
+    let width = state.screen.width.saturating_sub(left);
+    let replacing = state.results.replacing;
+    let column = match replacing {
+        true => width.saturating_sub(2) / 2,
+        false => width,
+    };
+    let separator = "│".with(state.palette.muted).to_string();
+    let replaced = "replaced".with(state.palette.replace).to_string();
+    let title = (path.bold().to_string(), replaced);
+    for (i, (before, after)) in [&title].into_iter().chain(rows).enumerate() {
+        let y = (RESULTS_TOP + i) as u16;
+        out.print_at(left as u16, y, &clip(before, column.saturating_sub(1)))?;
+        if replacing {
+            out.print_at((left + column) as u16, y, &separator)?;
+            out.print_at((left + column + 2) as u16, y, &clip(after, column))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn screen_for(query: &str, lines: usize) -> AppState {
+    use super::file_store::FileMeta;
+    use super::search::{FileChanges, SearchMessage, SearchOptions, SearchOutcome, SearchResults};
+    use super::terminal::ScreenSize;
+
+    let screen = ScreenSize {
+        width: 40,
+        height: 14,
+    };
+    let mut state = AppState::new(query, SearchOptions::default(), screen);
+    let meta = FileMeta {
+        size: 10,
+        modified: None,
+    };
+    let generation = state.next_generation();
+    state.apply(SearchMessage {
+        generation,
+        glob: "src/*".to_string(),
+        outcome: Ok(SearchOutcome {
+            listing: vec![],
+            files: vec![
+                ("src/a.rs".to_string(), meta.clone()),
+                ("src/b.rs".to_string(), meta),
+            ],
+            skipped: 1,
+            results: SearchResults {
+                files: vec![
+                    FileChanges {
+                        path: "src/a.rs".to_string(),
+                        lines: (1..=lines)
+                            .map(|i| format!("{: >4} fn f{}()", i, i))
+                            .collect(),
+                        changes: lines,
+                        bytes: 1024,
+                        ..FileChanges::default()
+                    },
+                    FileChanges {
+                        path: "src/b.rs".to_string(),
+                        bytes: 1024,
+                        ..FileChanges::default()
+                    },
+                ],
+                searched: true,
+                replacing: false,
+                elapsed: Duration::from_millis(2),
+                ..SearchResults::default()
+            },
+        }),
+    });
+    state
+}
+
+#[cfg(test)]
+fn draw(state: &AppState) -> Vec<String> {
+    use super::renderer::BufferRenderer;
+
+    let mut buffer = BufferRenderer::new(state.screen);
+    print_help(&mut buffer, state).unwrap();
+    print_prompt(&mut buffer, state).unwrap();
+    render_results(&mut buffer, state).unwrap();
+    buffer.lines()
+}
+
+#[test]
+fn render_results_screen() {
+    let state = screen_for("src/* fn", 3);
+    assert_eq!(
+        draw(&state),
+        vec![
+            "CTRL+S write · CTRL+Z undo · TAB next fi",
+            "glob    │ src/*",
+            "search  │ fn█",
+            "replace │",
+            "",
+            "",
+            "╭ src/a.rs                             █",
+            "│    1 fn f1()                         ░",
+            "│    2 fn f2()                         ░",
+            "│    3 fn f3()                         ░",
+            "╰──────── 3 matches                    ░",
+            "                                       ░",
+            "                                       ░",
+            "3 matches in 1/2 files           F1 keys",
+        ]
+    );
+}
+
+#[test]
+fn render_scrolled_results_screen() {
+    let mut state = screen_for("src/* fn", 30);
+    state.scroll_by(10);
+    assert_eq!(
+        draw(&state),
+        vec![
+            "CTRL+S write · CTRL+Z undo · TAB next fi",
+            "glob    │ src/*",
+            "search  │ fn█",
+            "replace │",
+            "",
+            "",
+            "│   10 fn f10()                        ░",
+            "│   11 fn f11()                        ░",
+            "│   12 fn f12()                        █",
+            "│   13 fn f13()                        ░",
+            "│   14 fn f14()                        ░",
+            "│   15 fn f15()                        ░",
+            "│   16 fn f16()                        ░",
+            "30 matches in 1/2 files          F1 keys",
+        ]
+    );
+}
+
+#[test]
+fn render_resized_screen() {
+    use super::renderer::BufferRenderer;
+    use super::terminal::ScreenSize;
+
+    let mut state = screen_for("src/* fn", 3);
+    let mut buffer = BufferRenderer::new(state.screen);
+    render_screen(&mut buffer, &state).unwrap();
+
+    state.screen = ScreenSize {
+        width: 24,
+        height: 9,
+    };
+    buffer = BufferRenderer::new(state.screen);
+    render_screen(&mut buffer, &state).unwrap();
+    assert_eq!(
+        buffer.lines(),
+        vec![
+            "CTRL+S write · CTRL+Z un",
+            "glob    │ src/*",
+            "search  │ fn█",
+            "replace │",
+            "",
+            "",
+            "╭ src/a.rs             █",
+            "│    1 fn f1()         ░",
+            "3 matches in 1/2 files",
+        ]
+    );
+
+    state.screen = ScreenSize {
+        width: 10,
+        height: 3,
+    };
+    buffer = BufferRenderer::new(state.screen);
+    render_screen(&mut buffer, &state).unwrap();
+}
+
+#[test]
+fn print_status_bar() {
+    use super::renderer::BufferRenderer;
+    use super::terminal::ScreenSize;
+
+    let mut state = screen_for("src/* fn -i", 3);
+    state.prompt.compact = true;
+    state.prompt.set_query("src/* fn -i");
+    state.screen = ScreenSize {
+        width: 140,
+        height: 14,
+    };
+    let mut buffer = BufferRenderer::new(state.screen);
+    print_status(&mut buffer, &state).unwrap();
+    let status = &buffer.lines()[13];
+    assert!(status.starts_with(
+        "3 matches in 1/2 files · 1 skipped, too large or not text · ignore case · 2 files, 2.0 KiB in 2 ms"
+    ));
+    assert!(status.ends_with(" F1 keys"));
+    assert_eq!(status.width(), 140);
+
+    state.last_error = Some("invalid glob".to_string());
+    state.screen.width = 60;
+    let mut buffer = BufferRenderer::new(state.screen);
+    print_status(&mut buffer, &state).unwrap();
+    let status = &buffer.lines()[13];
+    assert!(status.starts_with("error: invalid glob · 3 matches in 1/2 files  "));
+    assert!(status.ends_with(" F1 keys"));
+}
+
+#[test]
+fn render_help_over_results() {
+    let mut state = screen_for("src/* fn", 3);
+    state.screen.width = 100;
+    state.screen.height = 24;
+    state.perform(Action::Help);
+    let lines = draw(&state);
+    assert!(lines[RESULTS_TOP].contains("╭─ keys ─"));
+    assert!(lines[RESULTS_TOP + 1].contains("│ ESC CTRL+C    exit, or close the help"));
+    assert!(lines.iter().any(|line| line.contains("F1 ALT+H")));
+    let bottom = RESULTS_TOP + 1 + state.help_rows();
+    assert!(lines[bottom].contains("╰──"));
+    assert_eq!(lines[RESULTS_TOP].width(), lines[bottom].width());
+
+    // Rows that do not fit are scrolled, and quitting closes the help.
+    state.screen.width = 60;
+    state.screen.height = 12;
+    assert!(!state.perform(Action::ScrollUp));
+    assert!(state.perform(Action::PageDown));
+    let lines = draw(&state);
+    assert!(lines[RESULTS_TOP + 1].contains("PgUp"));
+    assert!(lines[RESULTS_TOP + 4].contains(" 6/27 "));
+    assert!(state.perform(Action::Quit));
+    assert!(!state.show_help);
+}
+
+#[test]
+fn render_theme_picker_over_results() {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    let mut state = screen_for("src/* fn", 3);
+    state.options.theme = Some("Nord".to_string());
+    state.perform(Action::PickTheme);
+    let lines = draw(&state);
+    assert!(lines[RESULTS_TOP].contains("╭─ theme ─"));
+    assert!(lines.iter().any(|line| line.contains("▸ Nord")));
+
+    let up = KeyEvent::new(KeyCode::Up, KeyModifiers::NONE);
+    assert!(state.handle_theme_picker_key(up));
+    let lines = draw(&state);
+    assert!(lines.iter().any(|line| line.contains("  Nord")));
+    assert!(!lines.iter().any(|line| line.contains("▸ Nord")));
+}
+
+#[test]
+fn print_prompt_at_cursor() {
+    use super::renderer::BufferRenderer;
+    use super::search::SearchOptions;
+    use super::terminal::ScreenSize;
+
+    let screen = ScreenSize {
+        width: 12,
+        height: 3,
+    };
+    let mut state = AppState::new("src/**/* 'fn main' run", SearchOptions::default(), screen);
+    state.prompt.compact = true;
+    let mut buffer = BufferRenderer::new(screen);
+    print_prompt(&mut buffer, &state).unwrap();
+    assert_eq!(buffer.lines()[2], "n main' run█");
+
+    state.prompt.line.move_home();
+    print_prompt(&mut buffer, &state).unwrap();
+    assert_eq!(buffer.lines()[2], "src/**/* 'fn");
+    state.prompt.line.move_word_right();
+    state.prompt.line.move_right();
+    print_prompt(&mut buffer, &state).unwrap();
+    assert_eq!(buffer.lines()[2], "src/**/* 'fn");
+    assert_eq!(buffer.lines()[2].chars().nth(9), Some('\''));
+    assert_eq!(buffer.lines()[2].chars().nth(11), Some('n'));
+}
+
+#[test]
+fn print_form_fields() {
+    use super::renderer::BufferRenderer;
+    use super::search::SearchOptions;
+    use super::terminal::ScreenSize;
+
+    let screen = ScreenSize {
+        width: 20,
+        height: 5,
+    };
+    let mut state = AppState::new("src/**/* 'fn main' run", SearchOptions::default(), screen);
+    let mut buffer = BufferRenderer::new(screen);
+    print_prompt(&mut buffer, &state).unwrap();
+    assert_eq!(
+        buffer.lines(),
+        vec![
+            "",
+            "glob    │ src/**/*",
+            "search  │ fn main█",
+            "replace │ run",
+            ""
+        ]
+    );
+
+    state.prompt.cycle_focus(false);
+    state.prompt.editor_mut().insert(" a long replacement");
+    print_prompt(&mut buffer, &state).unwrap();
+    assert_eq!(buffer.lines()[2], "search  │ fn main");
+    assert_eq!(buffer.lines()[3], "replace │ placement█");
+}
+
+#[test]
+fn print_parse_errors() {
+    use super::renderer::BufferRenderer;
+    use super::search::SearchOptions;
+    use super::terminal::ScreenSize;
+
+    let screen = ScreenSize {
+        width: 30,
+        height: 5,
+    };
+    let mut state = AppState::new("src/* -i 'fn", SearchOptions::default(), screen);
+    state.prompt.compact = true;
+    let mut buffer = BufferRenderer::new(screen);
+    print_prompt(&mut buffer, &state).unwrap();
+    assert_eq!(
+        buffer.lines(),
+        vec![
+            "",
+            "",
+            "src/* -i 'fn█",
+            "         ^ missing closing quo",
+            ""
+        ]
+    );
+}
+
+#[test]
+fn print_history_search() {
+    use super::history::HistorySearch;
+    use super::line_editor::LineEditor;
+    use super::renderer::BufferRenderer;
+    use super::search::SearchOptions;
+    use super::terminal::ScreenSize;
+
+    let screen = ScreenSize {
+        width: 40,
+        height: 4,
+    };
+    let mut state = AppState::new("src/* fn", SearchOptions::default(), screen);
+    state.history.push("src/* 'fn main'", false).unwrap();
+    state.history_search = Some(HistorySearch {
+        term: LineEditor::new("main"),
+        found: Some(0),
+    });
+    let mut buffer = BufferRenderer::new(screen);
+    print_prompt(&mut buffer, &state).unwrap();
+    assert_eq!(buffer.lines()[2], "history search: main█ src/* 'fn main'");
+}
+
+#[test]
+fn render_split_layout() {
+    use super::replacer::Replacer;
+    use super::search::{FileChanges, SearchMessage, SearchOptions, SearchOutcome, SearchResults};
+    use super::temp_dir::TempDir;
+    use super::terminal::ScreenSize;
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let _entered = runtime.enter();
+    let dir = TempDir::new("render-split-layout");
+    let path = dir.file("a.txt");
+    let content = "a\nb\nc\n";
+    std::fs::write(&path, content).unwrap();
+
+    let screen = ScreenSize {
+        width: 120,
+        height: 12,
+    };
+    let mut state = AppState::new(&format!("{} b x", path), SearchOptions::default(), screen);
+    let split = state.split();
+    assert!(state.needs_search(&split));
+    let (lines, changes) = Replacer::new("b", "x").preview(content);
+    let generation = state.next_generation();
+    state.apply(SearchMessage {
+        generation,
+        glob: path.clone(),
+        outcome: Ok(SearchOutcome {
+            listing: vec![],
+            files: vec![],
+            skipped: 0,
+            results: SearchResults {
+                files: vec![FileChanges {
+                    path: path.clone(),
+                    lines,
+                    changes,
+                    bytes: content.len() as u64,
+                    ..FileChanges::default()
+                }],
+                searched: true,
+                replacing: true,
+                elapsed: Duration::from_millis(1),
+                ..SearchResults::default()
+            },
+        }),
+    });
+    // Rendered off the event loop, only the title is shown until then.
+    assert!(state.rendering_pane());
+    assert!(draw(&state)[RESULTS_TOP + 2].ends_with("░"));
+    while !state.receive_pane() {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert!(!state.rendering_pane());
+    let lines = draw(&state);
+    assert!(lines[RESULTS_TOP].ends_with(" │ replaced"));
+    assert!(lines[RESULTS_TOP + 2].starts_with("│ 2 bx  "));
+    assert!(lines[RESULTS_TOP + 2].contains("░ 2 b  "));
+    assert!(lines[RESULTS_TOP + 2].ends_with(" │ 2 x"));
+
+    // Hidden, then too narrow to be shown.
+    assert!(state.perform(Action::ToggleSplit));
+    assert!(draw(&state)[RESULTS_TOP + 2].ends_with("░"));
+    state.resize(ScreenSize {
+        width: 80,
+        height: 12,
+    });
+    assert!(state.perform(Action::ToggleSplit));
+    assert!(state.notice.as_ref().unwrap().contains("from 120 columns"));
+    assert!(draw(&state)[RESULTS_TOP + 2].ends_with("░"));
+}
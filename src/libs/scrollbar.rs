@@ -1,7 +1,13 @@
-use crossterm::execute;
-use std::io::{stdout, Error};
+use std::io::Error;
+
+#[cfg(test)]
+use super::renderer::BufferRenderer;
+use super::renderer::Renderer;
+#[cfg(test)]
+use super::terminal::ScreenSize;
 
 pub fn display_scrollbar(
+    out: &mut dyn Renderer,
     offset: usize,
     total: usize,
     top: usize,
@@ -12,21 +18,21 @@ pub fn display_scrollbar(
 
     for i in 0..height {
         let char = if i == carret_position as usize {
-            '█'
+            "█"
         } else {
-            '░'
+            "░"
         };
-        execute!(
-            stdout(),
-            crossterm::cursor::MoveTo(left as u16, (top + i) as u16),
-            crossterm::style::Print(char),
-        )?;
+        out.print_at(left as u16, (top + i) as u16, char)?;
     }
     Ok(())
 }
 
 #[test]
 fn display_scrollbar_test() {
-    let result = display_scrollbar(0, 10, 0, 10, 0);
-    assert!(result.is_ok());
+    let mut buffer = BufferRenderer::new(ScreenSize {
+        width: 2,
+        height: 4,
+    });
+    display_scrollbar(&mut buffer, 5, 10, 0, 4, 1).unwrap();
+    assert_eq!(buffer.lines(), vec![" ░", " ░", " █", " ░"]);
 }
@@ -0,0 +1,397 @@
+use std::io::Error;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use glob::Pattern;
+use tokio::task::JoinSet;
+
+use super::decorate_file_content::DiffStyle;
+use super::file_store::{list_files, Contents, FileMeta};
+use super::replacer::Replacer;
+use super::theme::ColorDepth;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SearchOptions {
+    pub eol_insensitive: bool,
+    pub max_filesize: u64,
+    pub case_insensitive: bool,
+    pub whole_word: bool,
+    /// The query is a regular expression.
+    pub regex: bool,
+    /// Extensions of the files searched, every file when empty.
+    pub extensions: Vec<String>,
+    /// Patterns of the files left out.
+    pub exclude: Vec<Pattern>,
+    /// Unchanged lines shown around changes.
+    pub context: usize,
+    /// Syntax highlighting theme of the previews, plain when `None`.
+    pub theme: Option<String>,
+    /// Colours the previews are converted to.
+    pub color_depth: ColorDepth,
+    /// How replacements are shown in the previews.
+    pub diff: DiffStyle,
+}
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions {
+            eol_insensitive: false,
+            max_filesize: u64::MAX,
+            case_insensitive: false,
+            whole_word: false,
+            regex: false,
+            extensions: vec![],
+            exclude: vec![],
+            context: 0,
+            theme: None,
+            color_depth: ColorDepth::default(),
+            diff: DiffStyle::default(),
+        }
+    }
+}
+
+impl SearchOptions {
+    pub fn exceeds_max_filesize(&self, size: u64) -> bool {
+        size > self.max_filesize
+    }
+
+    /// Whether `path` has one of the extensions searched and is not excluded.
+    pub fn includes(&self, path: &str) -> bool {
+        let extension = Path::new(path).extension().and_then(|e| e.to_str());
+        let typed = self.extensions.is_empty()
+            || extension.is_some_and(|extension| self.extensions.iter().any(|e| e == extension));
+        typed && !self.exclude.iter().any(|pattern| pattern.matches(path))
+    }
+}
+
+/// Rendered changes of a single file, as produced by the matching stage.
+#[derive(Debug, Default, Clone)]
+pub struct FileChanges {
+    pub path: String,
+    pub lines: Vec<String>,
+    /// Indexes in `lines` of the changed lines, which can be unselected,
+    /// with the indexes of the matches they show.
+    pub matched: Vec<(usize, Range<usize>)>,
+    pub changes: usize,
+    pub bytes: u64,
+}
+
+/// Outcome of the matching stage over every listed file.
+#[derive(Debug, Default, Clone)]
+pub struct SearchResults {
+    pub files: Vec<FileChanges>,
+    /// Files left out for not being readable as UTF-8 text.
+    pub unreadable: usize,
+    pub searched: bool,
+    pub replacing: bool,
+    pub elapsed: Duration,
+}
+
+impl SearchResults {
+    pub fn replaced_count(&self) -> usize {
+        self.files.iter().map(|file| file.changes).sum()
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.files.iter().filter(|file| file.changes != 0).count()
+    }
+
+    pub fn scanned_bytes(&self) -> u64 {
+        self.files.iter().map(|file| file.bytes).sum()
+    }
+}
+
+/// Progress of a running search, updated by its tasks and read by the
+/// event loop to show it.
+#[derive(Debug)]
+pub struct ScanProgress {
+    pub started: Instant,
+    /// The files were listed by a previous search.
+    pub cached: bool,
+    /// Files to scan, known once they are listed.
+    pub files: AtomicUsize,
+    pub scanned: AtomicUsize,
+}
+
+impl Default for ScanProgress {
+    fn default() -> ScanProgress {
+        ScanProgress::new(false)
+    }
+}
+
+impl ScanProgress {
+    pub fn new(cached: bool) -> ScanProgress {
+        ScanProgress {
+            started: Instant::now(),
+            cached,
+            files: AtomicUsize::new(0),
+            scanned: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn files(&self) -> usize {
+        self.files.load(Ordering::Relaxed)
+    }
+
+    pub fn scanned(&self) -> usize {
+        self.scanned.load(Ordering::Relaxed)
+    }
+}
+
+/// What a background search sends back to the event loop.
+pub struct SearchMessage {
+    pub generation: usize,
+    pub glob: String,
+    pub outcome: Result<SearchOutcome, String>,
+}
+
+pub struct SearchOutcome {
+    pub listing: Vec<(String, FileMeta)>,
+    pub files: Vec<(String, FileMeta)>,
+    /// Files left out for their size or for not being readable as text.
+    pub skipped: usize,
+    pub results: SearchResults,
+}
+
+/// Renders the changes of the file at `path`, none when it cannot be read
+/// as UTF-8 text.
+pub fn display_changes_in_file(
+    contents: &Contents,
+    replacer: Option<&Replacer>,
+    replacing: bool,
+    path: &str,
+) -> Option<FileChanges> {
+    let file = contents.get_file(path).ok()?;
+    let content = file.as_str().ok()?;
+    let bytes = content.len() as u64;
+
+    let replacer = match replacer {
+        Some(replacer) => replacer,
+        None => {
+            return Some(FileChanges {
+                path: path.to_string(),
+                lines: content.lines().map(|x| x.to_string()).collect(),
+                matched: vec![],
+                changes: 0,
+                bytes,
+            })
+        }
+    };
+
+    let syntax = replacer.syntax(path, content);
+    let (preview, changes) = replacer.render(content, replacing, syntax.as_ref());
+    Some(FileChanges {
+        path: path.to_string(),
+        lines: preview.lines,
+        matched: preview.matched,
+        changes,
+        bytes,
+    })
+}
+
+fn scan_concurrency() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+}
+
+/// Files left to scan, taken one at a time by the workers of a scan.
+struct ScanQueue {
+    files: Vec<String>,
+    next: AtomicUsize,
+    /// Set when the scan is dropped, as when its search is cancelled.
+    stopped: AtomicBool,
+}
+
+impl ScanQueue {
+    /// Index and path of the next file, none once every file is taken or
+    /// the scan is stopped.
+    fn pop(&self) -> Option<(usize, &str)> {
+        if self.stopped.load(Ordering::Relaxed) {
+            return None;
+        }
+        let i = self.next.fetch_add(1, Ordering::Relaxed);
+        self.files.get(i).map(|file| (i, file.as_str()))
+    }
+}
+
+/// Stops the workers of a scan after their current file when dropped.
+struct StopScan(Arc<ScanQueue>);
+
+impl Drop for StopScan {
+    fn drop(&mut self) {
+        self.0.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Reads and matches `files` on `scan_concurrency` blocking workers, off the
+/// threads of the event loop, counting them in `progress`. Each worker takes
+/// the next file once done with the previous one. Results are returned in
+/// the order of `files`, without the files that cannot be read as text.
+pub async fn scan_files(
+    contents: &Contents,
+    files: &[String],
+    search: Option<String>,
+    substitute: Option<String>,
+    options: &SearchOptions,
+    progress: &Arc<ScanProgress>,
+) -> Result<SearchResults, Error> {
+    let started = Instant::now();
+    progress.files.store(files.len(), Ordering::Relaxed);
+    let replacing = substitute.is_some();
+    let searched = search.is_some();
+    let substitute = substitute.unwrap_or_default();
+    let replacer =
+        search.map(|search| Arc::new(Replacer::new(&search, &substitute).options(options.clone())));
+    if let Some(replacer) = &replacer {
+        replacer.validate()?;
+    }
+    let queue = Arc::new(ScanQueue {
+        files: files.to_vec(),
+        next: AtomicUsize::new(0),
+        stopped: AtomicBool::new(false),
+    });
+    let _stop = StopScan(queue.clone());
+    let mut workers = JoinSet::new();
+    for _ in 0..scan_concurrency().min(files.len()) {
+        let queue = queue.clone();
+        let contents = contents.clone();
+        let replacer = replacer.clone();
+        let progress = progress.clone();
+        workers.spawn_blocking(move || {
+            let mut results = vec![];
+            while let Some((i, file)) = queue.pop() {
+                let result =
+                    display_changes_in_file(&contents, replacer.as_deref(), replacing, file);
+                progress.scanned.fetch_add(1, Ordering::Relaxed);
+                results.push((i, result));
+            }
+            results
+        });
+    }
+
+    let mut results: Vec<Option<FileChanges>> = files.iter().map(|_| None).collect();
+    while let Some(joined) = workers.join_next().await {
+        for (i, result) in joined.map_err(Error::other)? {
+            results[i] = result;
+        }
+    }
+    let scanned = results.len();
+    let files = results.into_iter().flatten().collect::<Vec<FileChanges>>();
+    Ok(SearchResults {
+        unreadable: scanned - files.len(),
+        files,
+        searched,
+        replacing,
+        elapsed: started.elapsed(),
+    })
+}
+
+/// Runs both stages of a search: discovery of the files of `glob` (skipped
+/// when `listing` comes from the cache) then matching.
+pub async fn run_search(
+    glob: &str,
+    listing: Option<Vec<(String, FileMeta)>>,
+    search: Option<String>,
+    substitute: Option<String>,
+    contents: &Contents,
+    options: &SearchOptions,
+    progress: &Arc<ScanProgress>,
+) -> Result<SearchOutcome, Error> {
+    let listing = match listing {
+        Some(listing) => listing,
+        None => list_files(glob)?,
+    };
+    let (files, skipped): (Vec<_>, Vec<_>) = listing
+        .iter()
+        .filter(|(name, _)| options.includes(name))
+        .cloned()
+        .partition(|(_, meta)| !options.exceeds_max_filesize(meta.size));
+    let names = files
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<String>>();
+    let results = scan_files(contents, &names, search, substitute, options, progress).await?;
+    Ok(SearchOutcome {
+        listing,
+        files,
+        skipped: skipped.len() + results.unreadable,
+        results,
+    })
+}
+
+#[test]
+fn filter_files_by_type_and_pattern() {
+    let options = SearchOptions {
+        extensions: vec!["rs".to_string()],
+        exclude: vec![Pattern::new("target/**").unwrap()],
+        ..SearchOptions::default()
+    };
+    assert!(options.includes("src/main.rs"));
+    assert!(!options.includes("README.md"));
+    assert!(!options.includes("target/debug/build.rs"));
+    assert!(SearchOptions::default().includes("Makefile"));
+}
+
+#[test]
+fn scan_files_in_order() {
+    use super::temp_dir::TempDir;
+
+    let dir = TempDir::new("scan-files-in-order");
+    let files = (0..20)
+        .map(|i| {
+            let path = dir.file(&format!("{}.txt", i));
+            std::fs::write(&path, "a\n".repeat(i)).unwrap();
+            path
+        })
+        .collect::<Vec<String>>();
+    let progress = Arc::new(ScanProgress::default());
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let results = runtime
+        .block_on(scan_files(
+            &Contents::default(),
+            &files,
+            Some("a".to_string()),
+            Some("b".to_string()),
+            &SearchOptions::default(),
+            &progress,
+        ))
+        .unwrap();
+    let changes = results.files.iter().map(|file| file.changes);
+    assert!(changes.eq(0..20));
+    assert!(results
+        .files
+        .iter()
+        .zip(&files)
+        .all(|(file, path)| &file.path == path));
+    assert_eq!(progress.scanned(), 20);
+}
+
+#[test]
+fn skip_unreadable_files() {
+    use super::temp_dir::TempDir;
+
+    let dir = TempDir::new("skip-unreadable-files");
+    std::fs::write(dir.file("text.txt"), "a").unwrap();
+    std::fs::write(dir.file("binary.txt"), b"a\xff").unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let outcome = runtime
+        .block_on(run_search(
+            &format!("{}/*.txt", dir.to_str().unwrap()),
+            None,
+            Some("a".to_string()),
+            None,
+            &Contents::default(),
+            &SearchOptions::default(),
+            &Arc::new(ScanProgress::default()),
+        ))
+        .unwrap();
+    assert_eq!(outcome.skipped, 1);
+    assert_eq!(outcome.results.unreadable, 1);
+    assert_eq!(outcome.results.files.len(), 1);
+    assert_eq!(outcome.results.files[0].path, dir.file("text.txt"));
+    assert_eq!(outcome.results.replaced_count(), 1);
+}
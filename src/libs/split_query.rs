@@ -1,8 +1,33 @@
-use std::io::Error;
+use std::ops::Range;
 
-use crossterm::style::Stylize;
+use glob::Pattern;
+use regex::Regex;
 
-use super::terminal::{clear_lines, print_at};
+use super::search::SearchOptions;
+
+/// Characters that can follow a backslash: `\"`, `\'`, `\n`, `\t`, `\\` and
+/// an escaped space. Other backslashes are kept as typed.
+const ESCAPED: &str = "\"'nt\\ ";
+/// Flags accepted after a `/regex/`.
+const REGEX_FLAGS: &str = "imsxU";
+/// File types of the `t:` modifier and their extensions.
+const FILE_TYPES: &[(&str, &[&str])] = &[
+    ("c", &["c", "h"]),
+    ("cpp", &["cpp", "cc", "cxx", "hpp", "hh", "h"]),
+    ("css", &["css", "scss"]),
+    ("go", &["go"]),
+    ("html", &["html", "htm"]),
+    ("java", &["java"]),
+    ("js", &["js", "mjs", "cjs", "jsx"]),
+    ("json", &["json"]),
+    ("md", &["md", "markdown"]),
+    ("py", &["py", "pyi"]),
+    ("rust", &["rs"]),
+    ("sh", &["sh", "bash", "zsh"]),
+    ("toml", &["toml"]),
+    ("ts", &["ts", "tsx"]),
+    ("yaml", &["yaml", "yml"]),
+];
 
 #[derive(Debug, PartialEq, Default, Clone)]
 pub struct QuerySplit {
@@ -10,84 +35,288 @@ pub struct QuerySplit {
     pub glob: Option<String>,
     pub search: Option<String>,
     pub replace: Option<String>,
+    pub modifiers: Modifiers,
+    pub errors: Vec<ParseError>,
 }
 
-impl QuerySplit {
-    pub fn display_with_colors(&self) -> String {
-        let mut display = String::new();
-        if let Some(glob) = &self.glob {
-            display.push_str(glob.to_string().stylize().bold().blue().to_string().as_str());
-        }
-        if let Some(search) = &self.search {
-            display.push(' ');
-            display.push_str(search.to_string().stylize().bold().yellow().to_string().as_str());
-        }
-        if let Some(replace) = &self.replace {
-            display.push(' ');
-            display.push_str(replace.to_string().stylize().bold().green().to_string().as_str());
-        }
-        display
+/// Options set inline with the `-i`, `-w`, `t:<type>`, `!<glob>` and
+/// `/regex/` tokens of a query.
+#[derive(Debug, PartialEq, Default, Clone)]
+pub struct Modifiers {
+    pub case_insensitive: bool,
+    pub whole_word: bool,
+    pub regex: bool,
+    pub extensions: Vec<String>,
+    pub exclude: Vec<Pattern>,
+}
+
+impl Modifiers {
+    /// `options` with the modifiers of the query added.
+    pub fn apply(&self, options: &SearchOptions) -> SearchOptions {
+        let mut options = options.clone();
+        options.case_insensitive |= self.case_insensitive;
+        options.whole_word |= self.whole_word;
+        options.regex |= self.regex;
+        options.extensions.extend(self.extensions.iter().cloned());
+        options.exclude.extend(self.exclude.iter().cloned());
+        options
     }
+}
 
-    pub fn len(&self) -> usize {
-        match self.query {
-            Some(ref query) => query.len(),
-            None => 0,
+/// Part of a query that could not be parsed, as a byte range.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+/// Role of a token, to highlight the query as typed.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TokenKind {
+    Glob,
+    Search,
+    Replace,
+    Modifier,
+}
+
+/// Token of a query: `span` covers its quotes and escapes, which are
+/// resolved in `text`.
+struct Token {
+    span: Range<usize>,
+    text: String,
+    quoted: bool,
+}
+
+/// Splits `query` on unquoted spaces. Quotes may be mixed within a token,
+/// the other kind of quote being literal between them.
+fn tokenize(query: &str, errors: &mut Vec<ParseError>) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut current: Option<Token> = None;
+    let mut quote: Option<(char, usize)> = None;
+    let mut chars = query.char_indices().peekable();
+
+    while let Some((i, char)) = chars.next() {
+        if char == ' ' && quote.is_none() {
+            tokens.extend(current.take());
+            continue;
+        }
+        let token = current.get_or_insert(Token {
+            span: i..i,
+            text: String::new(),
+            quoted: false,
+        });
+        let mut end = i + char.len_utf8();
+        match char {
+            '\\' => match chars.next_if(|(_, next)| ESCAPED.contains(*next)) {
+                Some((j, escaped)) => {
+                    end = j + escaped.len_utf8();
+                    token.text.push(match escaped {
+                        'n' => '\n',
+                        't' => '\t',
+                        escaped => escaped,
+                    });
+                }
+                None => token.text.push(char),
+            },
+            '"' | '\'' if quote.is_none() => {
+                quote = Some((char, i));
+                token.quoted = true;
+            }
+            char if quote.map(|(quote, _)| quote) == Some(char) => quote = None,
+            char => token.text.push(char),
         }
+        token.span.end = end;
     }
-
-    pub fn print(&self) -> Result<(), Error> {
-        clear_lines(&[2])?;
-        let content = self.display_with_colors();
-        print_at(0, 2, &content)?;
-        self.restore_cursor()
+    if let Some((_, start)) = quote {
+        errors.push(ParseError {
+            span: start..query.len(),
+            message: "missing closing quote".to_string(),
+        });
     }
+    tokens.extend(current);
+    tokens
+}
 
-    pub fn restore_cursor(&self) -> Result<(), Error> {
-        print_at(self.len() as u16, 2, "█")
+/// Extensions of the files of type `name`, as in `t:rust`.
+pub fn file_type_extensions(name: &str) -> Option<Vec<String>> {
+    let (_, extensions) = FILE_TYPES.iter().find(|(file_type, _)| *file_type == name)?;
+    Some(extensions.iter().map(|extension| extension.to_string()).collect())
+}
 
+/// Applies `text` to `modifiers` if it is a modifier. Returns `None` for
+/// other tokens.
+fn parse_modifier(text: &str, modifiers: &mut Modifiers) -> Option<Result<(), String>> {
+    if let Some(flags) = text
+        .strip_prefix('-')
+        .filter(|flags| flags.starts_with(|c: char| c.is_ascii_alphabetic()))
+    {
+        for flag in flags.chars() {
+            match flag {
+                'i' => modifiers.case_insensitive = true,
+                'w' => modifiers.whole_word = true,
+                flag => {
+                    return Some(Err(format!(
+                        "unknown modifier -{}, quote the token to search for it",
+                        flag
+                    )))
+                }
+            }
+        }
+        return Some(Ok(()));
+    }
+    if let Some(name) = text.strip_prefix("t:") {
+        return Some(match file_type_extensions(name) {
+            Some(extensions) => {
+                modifiers.extensions.extend(extensions);
+                Ok(())
+            }
+            None => Err(format!("unknown file type {}", name)),
+        });
     }
+    if let Some(pattern) = text.strip_prefix('!') {
+        return Some(match Pattern::new(pattern) {
+            Ok(_) if pattern.is_empty() => Err("missing pattern to exclude".to_string()),
+            Ok(pattern) => {
+                modifiers.exclude.push(pattern);
+                Ok(())
+            }
+            Err(e) => Err(format!("invalid pattern to exclude: {}", e.msg)),
+        });
+    }
+    None
 }
 
-fn update_split_query(split_query: &mut QuerySplit, temp: &str) {
-    if temp.is_empty() {
-        return;
+/// Reads a `/regex/flags` token, as typed, into a pattern with the flags
+/// inlined. Returns `None` for other tokens.
+fn parse_regex(raw: &str) -> Option<Result<String, String>> {
+    let body = raw.strip_prefix('/')?;
+    let mut escaped = false;
+    let mut end = None;
+    for (i, char) in body.char_indices() {
+        match char {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '/' => {
+                end = Some(i);
+                break;
+            }
+            _ => (),
+        }
+    }
+    let end = match end {
+        Some(end) => end,
+        None => {
+            return Some(Err(
+                "missing closing / of the regular expression".to_string()
+            ))
+        }
+    };
+    let pattern = ["/", " ", "\"", "'"]
+        .iter()
+        .fold(body[..end].to_string(), |pattern, char| {
+            pattern.replace(&format!("\\{}", char), char)
+        });
+    let flags = &body[end + 1..];
+    if pattern.is_empty() {
+        return Some(Err("empty regular expression".to_string()));
     }
-    if split_query.glob.is_none() {
-        split_query.glob = Some(temp.to_string());
-    } else if split_query.search.is_none() {
-        split_query.search = Some(temp.to_string());
-    } else if split_query.replace.is_none() {
-        split_query.replace = Some(temp.to_string());
+    if let Some(flag) = flags.chars().find(|flag| !REGEX_FLAGS.contains(*flag)) {
+        return Some(Err(format!("unknown regular expression flag {}", flag)));
     }
+    let pattern = match flags.is_empty() {
+        true => pattern,
+        false => format!("(?{}){}", flags, pattern),
+    };
+    Some(match Regex::new(&pattern) {
+        Ok(_) => Ok(pattern),
+        Err(e) => Err(e
+            .to_string()
+            .lines()
+            .last()
+            .unwrap_or_default()
+            .trim_start_matches("error: ")
+            .to_string()),
+    })
 }
 
-pub fn split_query(query: &str) -> QuerySplit {
-    let mut temp = String::new();
-    let mut quote_char = None;
-    let mut split_query = QuerySplit::default();
-
-    for char in query.chars() {
-        if ['"', '\''].contains(&char) {
-            if quote_char.is_none() {
-                quote_char = Some(char);
-            } else if quote_char == Some(char) {
-                quote_char = None;
+fn parse(query: &str) -> (QuerySplit, Vec<(Range<usize>, TokenKind)>) {
+    let mut split = QuerySplit {
+        query: Some(query.to_string()),
+        ..QuerySplit::default()
+    };
+    let mut spans = vec![];
+    let mut positional = 0;
+
+    for token in tokenize(query, &mut split.errors) {
+        let span = token.span.clone();
+        let error = |message: String| ParseError {
+            span: span.clone(),
+            message,
+        };
+        if !token.quoted {
+            if let Some(result) = parse_modifier(&token.text, &mut split.modifiers) {
+                split.errors.extend(result.err().map(error));
+                spans.push((token.span, TokenKind::Modifier));
+                continue;
             }
+        }
+        // Only a quoted empty replacement counts, removing the matches.
+        if token.text.is_empty() && !(token.quoted && positional == 2) {
             continue;
         }
-
-        if char == ' ' && quote_char.is_none() {
-            update_split_query(&mut split_query, &temp);
-            temp.clear();
-        } else {
-            temp.push(char);
+        let mut text = token.text;
+        if positional == 1 && !token.quoted {
+            match parse_regex(&query[span.clone()]) {
+                Some(Ok(pattern)) => {
+                    split.modifiers.regex = true;
+                    text = pattern;
+                }
+                Some(Err(message)) => split.errors.push(error(message)),
+                None => (),
+            }
         }
+        let kind = match positional {
+            0 => TokenKind::Glob,
+            1 => TokenKind::Search,
+            2 => TokenKind::Replace,
+            _ => {
+                split.errors.push(error(
+                    "extra token, quote tokens containing spaces".to_string(),
+                ));
+                continue;
+            }
+        };
+        match kind {
+            TokenKind::Glob => split.glob = Some(text),
+            TokenKind::Search => split.search = Some(text),
+            _ => split.replace = Some(text),
+        }
+        spans.push((span, kind));
+        positional += 1;
     }
-    update_split_query(&mut split_query, &temp);
-    split_query.query = Some(query.to_string());
+    (split, spans)
+}
 
-    split_query
+/// Splits a query into its glob, search and replace tokens, in that order,
+/// with the modifiers found anywhere in it:
+///
+/// - quotes group words, and `\"`, `\'`, `\n`, `\t`, `\\` and `\ ` are
+///   escapes,
+/// - `-i` ignores case and `-w` matches whole words,
+/// - `t:rust` only searches the files of a type,
+/// - `!target/**` leaves out the files matching a pattern,
+/// - a `/regex/flags` search token is a regular expression,
+/// - a `""` replacement removes the matches.
+///
+/// Problems are reported in `errors` rather than ignored.
+pub fn split_query(query: &str) -> QuerySplit {
+    parse(query).0
+}
+
+/// Byte ranges of the tokens of `query` and their role, quotes included, so
+/// the query can be highlighted as typed.
+pub fn token_spans(query: &str) -> Vec<(Range<usize>, TokenKind)> {
+    parse(query).1
 }
 
 #[test]
@@ -100,6 +329,7 @@ fn handle_simple() {
             glob: Some("*".to_string()),
             search: Some("search".to_string()),
             replace: Some("replace".to_string()),
+            ..QuerySplit::default()
         }
     );
 }
@@ -114,6 +344,7 @@ fn handle_double_quotes() {
             glob: Some("*".to_string()),
             search: Some("search quotes".to_string()),
             replace: Some("replace".to_string()),
+            ..QuerySplit::default()
         }
     );
 }
@@ -128,6 +359,7 @@ fn handle_single_quotes() {
             glob: Some("*".to_string()),
             search: Some("search quotes".to_string()),
             replace: Some("replace".to_string()),
+            ..QuerySplit::default()
         }
     );
 }
@@ -142,6 +374,7 @@ fn handle_empty() {
             glob: None,
             search: None,
             replace: None,
+            ..QuerySplit::default()
         }
     );
 }
@@ -156,6 +389,7 @@ fn handle_empty_quotes() {
             glob: Some("src".to_string()),
             search: None,
             replace: None,
+            ..QuerySplit::default()
         }
     );
 }
@@ -170,39 +404,90 @@ fn ignore_additional_spaces() {
             glob: Some("*".to_string()),
             search: Some("search".to_string()),
             replace: Some("replace".to_string()),
+            ..QuerySplit::default()
         }
     );
 }
 
 #[test]
-fn ignore_additional_words() {
-    let query = "* search replace extra";
+fn report_additional_words() {
+    let split = split_query("* search replace extra");
+    assert_eq!(split.replace, Some("replace".to_string()));
+    assert_eq!(split.errors.len(), 1);
+    assert_eq!(split.errors[0].span, 17..22);
+}
+
+#[test]
+fn handle_escapes() {
+    let split = split_query(r#"* "say \"hi\"" a\tb\\c\ d"#);
+    assert_eq!(split.search, Some("say \"hi\"".to_string()));
+    assert_eq!(split.replace, Some("a\tb\\c d".to_string()));
     assert_eq!(
-        split_query(query),
-        QuerySplit {
-            query: Some(query.to_string()),
-            glob: Some("*".to_string()),
-            search: Some("search".to_string()),
-            replace: Some("replace".to_string()),
-        }
+        split_query(r"* 'it\'s' x\ny").replace,
+        Some("x\ny".to_string())
     );
+    assert_eq!(split_query(r#"* "a'b""#).search, Some("a'b".to_string()));
+    assert!(split_query(r"* \d").errors.is_empty());
 }
 
 #[test]
-fn query_display_with_colors() {
-    let query = QuerySplit {
-        query: Some("* search replace".to_string()),
-        glob: Some("*".to_string()),
-        search: Some("search".to_string()),
-        replace: Some("replace".to_string()),
+fn handle_modifiers() {
+    let split = split_query("-i src/* t:rust fn !target/** -w");
+    assert_eq!(split.glob, Some("src/*".to_string()));
+    assert_eq!(split.search, Some("fn".to_string()));
+    assert!(split.modifiers.case_insensitive && split.modifiers.whole_word);
+    assert_eq!(split.modifiers.extensions, vec!["rs".to_string()]);
+    assert_eq!(
+        split.modifiers.exclude,
+        vec![Pattern::new("target/**").unwrap()]
+    );
+    assert!(split.errors.is_empty());
+
+    let split = split_query("* \"-x\" '!a' -1");
+    assert_eq!(split.search, Some("-x".to_string()));
+    assert_eq!(split.replace, Some("!a".to_string()));
+    assert_eq!(split.modifiers, Modifiers::default());
+    assert_eq!(split.errors[0].span, 12..14);
+}
+
+#[test]
+fn handle_regex() {
+    let split = split_query(r"* /fn\s+\w+\/x/i y");
+    assert_eq!(split.search, Some(r"(?i)fn\s+\w+/x".to_string()));
+    assert!(split.modifiers.regex);
+    assert!(!split_query("/src/* /").modifiers.regex);
+    assert_eq!(split_query("/src/* fn").glob, Some("/src/*".to_string()));
+}
+
+#[test]
+fn report_parse_errors() {
+    let errors = |query: &str| {
+        split_query(query)
+            .errors
+            .into_iter()
+            .map(|error| error.span)
+            .collect::<Vec<Range<usize>>>()
     };
+    assert_eq!(errors("* \"abc"), vec![2..6]);
+    assert_eq!(errors("* /a(/ b"), vec![2..6]);
+    assert_eq!(errors("* /a/q"), vec![2..6]);
+    assert_eq!(errors("* /abc"), vec![2..6]);
+    assert_eq!(errors("t:cobol -z !"), vec![0..7, 8..10, 11..12]);
+}
+
+#[test]
+fn find_token_spans() {
+    use TokenKind::*;
+
+    assert_eq!(token_spans("src/* fn"), vec![(0..5, Glob), (6..8, Search)]);
     assert_eq!(
-        query.display_with_colors(),
-        format!(
-            "{} {} {}",
-            "*".to_string().stylize().bold().blue(),
-            "search".to_string().stylize().bold().yellow(),
-            "replace".to_string().stylize().bold().green()
-        )
+        token_spans("  a  'b c' \"\" -i d e"),
+        vec![
+            (2..3, Glob),
+            (5..10, Search),
+            (11..13, Replace),
+            (14..16, Modifier)
+        ]
     );
+    assert!(token_spans("").is_empty());
 }
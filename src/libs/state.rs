@@ -1,42 +1,1257 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io::Error;
+use std::ops::Range;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
 
-lazy_static! {
-    static ref STATE: Arc<Mutex<HashMap<String, String>>> = Arc::new(Mutex::new(HashMap::new()));
+use crossterm::event::{
+    KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+};
+use crossterm::style::Stylize;
+use tokio::sync::oneshot::{self, error::TryRecvError, Receiver};
+use tokio::task::JoinHandle;
+use unicode_width::UnicodeWidthStr;
+
+use super::decorate_file_content::{decorate_file_content, preview_line_number, DiffStyle};
+use super::file_store::FileStore;
+use super::history::{History, HistorySearch};
+use super::keymap::{Action, Keymap};
+use super::prompt::Prompt;
+use super::renderer::strip_styles;
+use super::replacer::Replacer;
+use super::search::{ScanProgress, SearchMessage, SearchOptions, SearchResults};
+use super::split_query::QuerySplit;
+use super::syntax_highlight::ThemePicker;
+use super::terminal::ScreenSize;
+use super::theme::Palette;
+
+/// How long an identical query is considered fresh enough not to search again.
+const SEARCH_TTL: Duration = Duration::from_secs(300);
+/// How long a query must stay searched to be kept in the history, leaving
+/// out the queries searched while typing.
+const HISTORY_DWELL: Duration = Duration::from_secs(2);
+/// First screen row of the results.
+pub const RESULTS_TOP: usize = 6;
+/// Lines scrolled by a turn of the mouse wheel.
+const WHEEL_STEP: isize = 3;
+/// Narrowest screen on which the first file in view is shown next to the
+/// results.
+const SPLIT_MIN_WIDTH: usize = 120;
+/// Largest file shown next to the results, as it is rendered whole.
+const PANE_MAX_BYTES: u64 = 1024 * 1024;
+
+/// A line of the results as drawn, `header` marking the first line of a file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ResultLine {
+    pub text: String,
+    pub header: bool,
+    /// Index of the file in the results.
+    pub file: usize,
+    /// Index of the changed line in the preview of the file, if any.
+    pub change: Option<usize>,
+}
+
+/// A file of the results rendered whole, to be shown next to them.
+struct FilePane {
+    /// Index of the file in the results.
+    file: usize,
+    /// The lines as they are and as they would be, once rendered.
+    rows: Vec<(String, String)>,
+    /// The rows being rendered off the event loop.
+    rendering: Option<Receiver<Vec<(String, String)>>>,
+}
+
+/// A file as it was before and after a write, to undo it.
+#[derive(Debug, Clone)]
+struct WrittenFile {
+    path: String,
+    before: String,
+    after: String,
+}
+
+/// State of an interactive session, owned by the event loop.
+#[derive(Default)]
+pub struct AppState {
+    pub prompt: Prompt,
+    pub options: SearchOptions,
+    pub files: FileStore,
+    pub results: SearchResults,
+    /// `results` flattened into the lines drawn, built once per search.
+    pub lines: Vec<ResultLine>,
+    pub scroll_offset: usize,
+    pub screen: ScreenSize,
+    pub last_error: Option<String>,
+    pub history: History,
+    /// Reverse search through the history, while CTRL+R is active.
+    pub history_search: Option<HistorySearch>,
+    pub keymap: Keymap,
+    /// Outcome of the last write or undo, shown above the results.
+    pub notice: Option<String>,
+    /// Shows the key bindings over the results.
+    pub show_help: bool,
+    /// First row of the help shown, when it does not fit.
+    pub help_scroll: usize,
+    /// Progress of the latest search.
+    pub progress: Arc<ScanProgress>,
+    /// Colours of the interface.
+    pub palette: Palette,
+    /// Choice of the syntax theme, while it is shown over the results.
+    pub theme_picker: Option<ThemePicker>,
+    /// Shows the first file in view next to the results on wide screens.
+    pub split: bool,
+    /// The first file in view, while the split layout is shown.
+    pane: Option<FilePane>,
+    /// Files folded to their header and footer.
+    collapsed: HashSet<usize>,
+    /// Matches the user unselected, as file and match indexes.
+    deselected: HashSet<(usize, usize)>,
+    dragging_scrollbar: bool,
+    generation: usize,
+    last_search: Option<(QuerySplit, Instant)>,
+    search_task: Option<JoinHandle<()>>,
+    /// Files of each write, the latest last.
+    writes: Vec<Vec<WrittenFile>>,
+}
+
+impl AppState {
+    pub fn new(query: &str, options: SearchOptions, screen: ScreenSize) -> AppState {
+        AppState {
+            prompt: Prompt::new(query, false),
+            options,
+            screen,
+            split: true,
+            ..AppState::default()
+        }
+    }
+
+    pub fn split(&self) -> QuerySplit {
+        self.prompt.split()
+    }
+
+    /// Records `split` as the latest search, unless the same tokens were
+    /// searched recently: extra spaces or quotes do not need a new search.
+    /// Queries that do not parse are not searched.
+    pub fn needs_search(&mut self, split: &QuerySplit) -> bool {
+        if !split.errors.is_empty() {
+            return false;
+        }
+        self.remember_search(false);
+        let unchanged = match &self.last_search {
+            Some((last, at)) => {
+                at.elapsed() < SEARCH_TTL
+                    && (&split.glob, &split.search, &split.replace, &split.modifiers)
+                        == (&last.glob, &last.search, &last.replace, &last.modifiers)
+            }
+            None => false,
+        };
+        self.last_search = Some((split.clone(), Instant::now()));
+        !unchanged
+    }
+
+    /// Keeps the latest search in the history if it was shown long enough,
+    /// or in any case with `force`, when leaving. Listing files only is not
+    /// worth remembering.
+    pub fn remember_search(&mut self, force: bool) {
+        if let Some((last, at)) = &self.last_search {
+            if last.search.is_some() && (force || at.elapsed() >= HISTORY_DWELL) {
+                let query = last.query.clone().unwrap_or_default();
+                // The history is best effort: an unwritable file is not
+                // worth interrupting the session.
+                let _ = self.history.push(&query, false);
+            }
+        }
+    }
+
+    /// Forgets the latest search, so that the same query is searched again
+    /// once files changed.
+    pub fn invalidate_search(&mut self) {
+        self.last_search = None;
+    }
+
+    /// While searching the history, keys edit the term searched, the key of
+    /// the search finds an older match, ENTER or TAB take the match and ESC
+    /// or CTRL+G cancel. Returns whether the key was used.
+    pub fn handle_history_search_key(&mut self, event: KeyEvent) -> bool {
+        if event.kind == KeyEventKind::Release {
+            return false;
+        }
+        let control = event.modifiers.contains(KeyModifiers::CONTROL);
+        let search = match &mut self.history_search {
+            Some(search) => search,
+            None => return false,
+        };
+        match event.code {
+            KeyCode::Char('c') if control => return false,
+            _ if self.keymap.action(event) == Some(Action::HistorySearch) => {
+                let before = search.found.unwrap_or(self.history.entries().len());
+                let term = search.term.text();
+                search.found = self.history.search(term, before).or(search.found);
+            }
+            KeyCode::Char('g') if control => self.history_search = None,
+            KeyCode::Esc => self.history_search = None,
+            KeyCode::Enter | KeyCode::Tab => {
+                if let Some(found) = search.found {
+                    let query = self.history.entries()[found].query.clone();
+                    self.prompt.set_query(&query);
+                }
+                self.history_search = None;
+            }
+            _ => {
+                if search.term.handle_key(event) {
+                    let latest = self.history.entries().len();
+                    search.found = self.history.search(search.term.text(), latest);
+                }
+            }
+        }
+        true
+    }
+
+    /// While choosing the theme, the scroll keys select one and preview it,
+    /// ENTER or the key of the choice keep it and ESC restores the previous
+    /// one. Other keys are ignored. Returns whether the key was used.
+    pub fn handle_theme_picker_key(&mut self, event: KeyEvent) -> bool {
+        if event.kind == KeyEventKind::Release {
+            return false;
+        }
+        let page = self.view_height().saturating_sub(2).max(1) as isize;
+        let action = self.keymap.action(event);
+        let picker = match &mut self.theme_picker {
+            Some(picker) => picker,
+            None => return false,
+        };
+        let rows = match (event.code, action) {
+            (KeyCode::Char('c'), _) if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                return false
+            }
+            (KeyCode::Enter, _) | (_, Some(Action::PickTheme)) => {
+                self.theme_picker = None;
+                return true;
+            }
+            (KeyCode::Esc, _) | (_, Some(Action::Quit)) => {
+                self.options.theme = picker.original.clone();
+                self.theme_picker = None;
+                self.invalidate_search();
+                return true;
+            }
+            (KeyCode::Up, _) | (_, Some(Action::ScrollUp)) => -1,
+            (KeyCode::Down, _) | (_, Some(Action::ScrollDown)) => 1,
+            (_, Some(Action::PageUp)) => -page,
+            (_, Some(Action::PageDown)) => page,
+            (_, Some(Action::Top)) => isize::MIN,
+            (_, Some(Action::Bottom)) => isize::MAX,
+            _ => return true,
+        };
+        if picker.select_by(rows) {
+            self.options.theme = Some(picker.selected().to_string());
+            self.invalidate_search();
+        }
+        true
+    }
+
+    /// Runs `action`, except quitting and opening an editor which are left
+    /// to the event loop; quitting closes the help first. Returns whether the
+    /// screen needs to be drawn again.
+    pub fn perform(&mut self, action: Action) -> bool {
+        if self.show_help {
+            let page = self.view_height().saturating_sub(2).max(1) as isize;
+            let lines = match action {
+                Action::ScrollUp => -1,
+                Action::ScrollDown => 1,
+                Action::PageUp => -page,
+                Action::PageDown => page,
+                _ => 0,
+            };
+            if lines != 0 {
+                let rows = self.help_rows();
+                let max_scroll = rows.saturating_sub(self.view_height().saturating_sub(2));
+                let scroll = self
+                    .help_scroll
+                    .saturating_add_signed(lines)
+                    .min(max_scroll);
+                let moved = scroll != self.help_scroll;
+                self.help_scroll = scroll;
+                return moved;
+            }
+        }
+        match action {
+            Action::ScrollUp => self.scroll_by(-1),
+            Action::ScrollDown => self.scroll_by(1),
+            Action::PageUp => self.page_up(),
+            Action::PageDown => self.page_down(),
+            Action::Top => self.scroll_to(0),
+            Action::Bottom => self.scroll_to(usize::MAX),
+            Action::NextFile => self.next_file(),
+            Action::PreviousFile => self.previous_file(),
+            Action::ToggleMatch => {
+                let first_change = self
+                    .visible_lines()
+                    .iter()
+                    .find_map(|line| Some((line.file, line.change?)));
+                match first_change {
+                    Some((file, change)) => self.toggle_change(file, change),
+                    None => return false,
+                }
+                true
+            }
+            Action::ToggleFold => match self.visible_lines().first().map(|line| line.file) {
+                Some(file) => {
+                    self.toggle_file(file);
+                    true
+                }
+                None => false,
+            },
+            Action::Write => {
+                let undo = self.keymap.label(Action::Undo).unwrap_or_default();
+                self.notice = Some(match self.write_changes() {
+                    _ if self.searching() => "Wait for the search to finish".to_string(),
+                    Ok(0) => "Nothing to write".to_string(),
+                    Ok(replaced) => format!("{} replacements written, {} to undo", replaced, undo),
+                    Err(e) => format!("Could not write: {}", e),
+                });
+                true
+            }
+            Action::Undo => {
+                self.notice = Some(match self.undo() {
+                    Ok(0) => "Nothing to undo".to_string(),
+                    Ok(restored) => format!("{} files restored", restored),
+                    Err(e) => format!("Could not undo: {}", e),
+                });
+                true
+            }
+            Action::Help => {
+                self.show_help = !self.show_help;
+                self.help_scroll = 0;
+                true
+            }
+            Action::Quit if self.show_help => {
+                self.show_help = false;
+                true
+            }
+            Action::NextField => self.prompt.cycle_focus(false),
+            Action::PreviousField => self.prompt.cycle_focus(true),
+            Action::ToggleCompact => {
+                self.prompt.toggle_compact();
+                true
+            }
+            Action::ToggleRegex => self.prompt.toggle_mode(|modifiers| &mut modifiers.regex),
+            Action::ToggleCase => self
+                .prompt
+                .toggle_mode(|modifiers| &mut modifiers.case_insensitive),
+            Action::ToggleWholeWord => self
+                .prompt
+                .toggle_mode(|modifiers| &mut modifiers.whole_word),
+            Action::HistoryPrevious | Action::HistoryNext => {
+                let recalled = match action {
+                    Action::HistoryPrevious => self.history.recall_previous(&self.prompt.query()),
+                    _ => self.history.recall_next(),
+                };
+                if let Some(query) = recalled.map(|query| query.to_string()) {
+                    self.prompt.set_query(&query);
+                }
+                true
+            }
+            Action::HistorySearch => {
+                self.history_search = Some(HistorySearch::default());
+                true
+            }
+            Action::ToggleDiff => {
+                self.options.diff = match self.options.diff {
+                    DiffStyle::Compact => DiffStyle::TwoLine,
+                    DiffStyle::TwoLine => DiffStyle::Compact,
+                };
+                self.invalidate_search();
+                true
+            }
+            Action::PickTheme => {
+                self.show_help = false;
+                self.theme_picker = Some(ThemePicker::new(self.options.theme.as_deref()));
+                true
+            }
+            Action::ToggleSplit => {
+                self.split = !self.split;
+                if self.split && self.screen.width < SPLIT_MIN_WIDTH {
+                    self.notice = Some(format!(
+                        "The file is shown beside the results from {} columns",
+                        SPLIT_MIN_WIDTH
+                    ));
+                }
+                self.update_pane();
+                true
+            }
+            Action::Quit | Action::OpenInEditor => false,
+        }
+    }
+
+    /// Writes the changes of the latest search to the files, like
+    /// `Replacer::write`, but for the matches the user unselected. Returns the
+    /// number of replacements made.
+    pub fn write_changes(&mut self) -> Result<usize, Error> {
+        let split = match &self.last_search {
+            Some((split, _)) if self.results.replacing && !self.searching() => split.clone(),
+            _ => return Ok(0),
+        };
+        let replacer = Replacer::new(
+            split.search.as_deref().unwrap_or_default(),
+            split.replace.as_deref().unwrap_or_default(),
+        )
+        .options(split.modifiers.apply(&self.options));
+        let mut written = vec![];
+        let mut replaced = 0;
+        let mut outcome = Ok(());
+        for (index, file) in self.results.files.iter().enumerate() {
+            if file.changes == 0 {
+                continue;
+            }
+            let skipped = self
+                .deselected
+                .iter()
+                .filter(|(deselected, _)| *deselected == index)
+                .map(|(_, found)| *found)
+                .collect::<HashSet<usize>>();
+            let before = match std::fs::read_to_string(&file.path) {
+                Ok(before) => before,
+                Err(_e) => continue,
+            };
+            let (after, changes) =
+                replacer.replace_matches(&before, |found| skipped.contains(&found));
+            if changes == 0 {
+                continue;
+            }
+            if let Err(e) = std::fs::write(&file.path, &after) {
+                outcome = Err(e);
+                break;
+            }
+            replaced += changes;
+            written.push(WrittenFile {
+                path: file.path.clone(),
+                before,
+                after,
+            });
+        }
+        if !written.is_empty() {
+            self.writes.push(written);
+            let _ = self
+                .history
+                .push(&split.query.clone().unwrap_or_default(), true);
+        }
+        self.invalidate_search();
+        outcome.map(|_| replaced)
+    }
+
+    /// Restores the files of the latest write, leaving those changed since.
+    /// Returns the number of files restored.
+    pub fn undo(&mut self) -> Result<usize, Error> {
+        let written = match self.writes.pop() {
+            Some(written) => written,
+            None => return Ok(0),
+        };
+        let mut restored = 0;
+        for file in written {
+            if std::fs::read_to_string(&file.path).ok() == Some(file.after) {
+                std::fs::write(&file.path, file.before)?;
+                restored += 1;
+            }
+        }
+        self.invalidate_search();
+        Ok(restored)
+    }
+
+    /// Rows of the help, laid out in as many columns as fit the screen.
+    pub fn help_rows(&self) -> usize {
+        let entries = self.keymap.help_entries(self.options.diff);
+        let keys = entries.iter().map(|(keys, _)| keys.width()).max();
+        let descriptions = entries
+            .iter()
+            .map(|(_, description)| description.width())
+            .max();
+        let entry_width = keys.unwrap_or(0) + 2 + descriptions.unwrap_or(0);
+        let columns = (self.screen.width.saturating_sub(2) / (entry_width + 2))
+            .clamp(1, entries.len().max(1));
+        entries.len().div_ceil(columns)
+    }
+
+    /// Index of the file of the first line in view, and the number of its
+    /// first changed line in view.
+    pub fn selection(&self) -> Option<(usize, usize)> {
+        let visible = self.visible_lines();
+        let file = visible.first()?.file;
+        let lines = &self.results.files[file].lines;
+        let line = visible
+            .iter()
+            .filter(|line| line.file == file)
+            .find_map(|line| preview_line_number(&lines[line.change?]))
+            .unwrap_or(1);
+        Some((file, line))
+    }
+
+    /// File of the selection and the line to open it at in an editor.
+    pub fn editor_target(&self) -> Option<(String, usize)> {
+        let (file, line) = self.selection()?;
+        Some((self.results.files[file].path.clone(), line))
+    }
+
+    /// Whether the screen is wide enough for the split layout, and it is on.
+    pub fn split_layout(&self) -> bool {
+        self.split && self.screen.width >= SPLIT_MIN_WIDTH
+    }
+
+    /// Columns of the results, scrollbar included: two fifths of the screen
+    /// in the split layout.
+    pub fn results_width(&self) -> usize {
+        match self.split_layout() {
+            true => self.screen.width * 2 / 5,
+            false => self.screen.width,
+        }
+    }
+
+    /// Path of the selected file and the `height` rows of it in view next to
+    /// the results, scrolled with the selection: its line is a third down.
+    pub fn pane_view(&self, height: usize) -> Option<(&str, &[(String, String)])> {
+        let pane = self.pane.as_ref()?;
+        let (file, line) = self.selection()?;
+        if file != pane.file {
+            return None;
+        }
+        let row = pane
+            .rows
+            .iter()
+            .position(|(left, _)| preview_line_number(left) == Some(line))
+            .unwrap_or(0);
+        let top = row
+            .saturating_sub(height / 3)
+            .min(pane.rows.len().saturating_sub(height));
+        let end = (top + height).min(pane.rows.len());
+        Some((&self.results.files[file].path, &pane.rows[top..end]))
+    }
+
+    /// Starts rendering the selected file for the split layout, when it is
+    /// shown and the selection moved to another file. The rows of the file
+    /// left are dropped with it.
+    fn update_pane(&mut self) {
+        if !self.split_layout() {
+            return;
+        }
+        let file = self.selection().map(|(file, _)| file);
+        if self.pane.as_ref().map(|pane| pane.file) == file {
+            return;
+        }
+        self.pane = file.map(|file| FilePane {
+            file,
+            rows: vec![],
+            rendering: self.render_pane(file),
+        });
+    }
+
+    /// Renders the whole of `file` on a blocking worker, as reading and
+    /// highlighting up to `PANE_MAX_BYTES` would hold up the event loop.
+    fn render_pane(&self, file: usize) -> Option<Receiver<Vec<(String, String)>>> {
+        let split = match &self.last_search {
+            Some((split, _)) if self.results.searched => split,
+            _ => return None,
+        };
+        let path = self.results.files[file].path.clone();
+        let replacer = Replacer::new(
+            split.search.as_deref().unwrap_or_default(),
+            split.replace.as_deref().unwrap_or_default(),
+        )
+        .options(split.modifiers.apply(&self.options));
+        let replacing = self.results.replacing;
+        let contents = self.files.contents();
+        let note = "too large to be shown whole".with(self.palette.muted);
+        let (sender, receiver) = oneshot::channel();
+        tokio::task::spawn_blocking(move || {
+            let size = std::fs::metadata(&path).map(|meta| meta.len());
+            if size.is_ok_and(|size| size > PANE_MAX_BYTES) {
+                let _ = sender.send(vec![(note.to_string(), String::new())]);
+                return;
+            }
+            let file = contents.get_file(&path);
+            let rows = match file.as_ref().map(|file| file.as_str()) {
+                Ok(Ok(content)) => {
+                    let syntax = replacer.syntax(&path, content);
+                    replacer.side_by_side(content, replacing, syntax.as_ref())
+                }
+                _ => vec![],
+            };
+            let _ = sender.send(rows);
+        });
+        Some(receiver)
+    }
+
+    /// Whether the file next to the results is still being rendered.
+    pub fn rendering_pane(&self) -> bool {
+        self.pane
+            .as_ref()
+            .is_some_and(|pane| pane.rendering.is_some())
+    }
+
+    /// Takes the rows of the file next to the results once rendered.
+    /// Returns whether they need to be drawn.
+    pub fn receive_pane(&mut self) -> bool {
+        let pane = match &mut self.pane {
+            Some(pane) => pane,
+            None => return false,
+        };
+        let rendering = match &mut pane.rendering {
+            Some(rendering) => rendering,
+            None => return false,
+        };
+        pane.rows = match rendering.try_recv() {
+            Ok(rows) => rows,
+            Err(TryRecvError::Empty) => return false,
+            // The worker panicked.
+            Err(TryRecvError::Closed) => vec![],
+        };
+        pane.rendering = None;
+        true
+    }
+
+    /// Starts a new search generation; results of older ones are ignored.
+    pub fn next_generation(&mut self) -> usize {
+        self.generation += 1;
+        self.generation
+    }
+
+    /// Keeps track of the task of the current search, aborting the previous
+    /// one. Dropping a search also aborts the file scans it spawned.
+    pub fn start_search(&mut self, task: JoinHandle<()>) {
+        if let Some(previous) = self.search_task.replace(task) {
+            previous.abort();
+        }
+    }
+
+    /// Aborts the current search, for queries that cannot be searched.
+    pub fn cancel_search(&mut self) {
+        if let Some(task) = self.search_task.take() {
+            task.abort();
+        }
+    }
+
+    pub fn searching(&self) -> bool {
+        match &self.search_task {
+            Some(task) => !task.is_finished(),
+            None => false,
+        }
+    }
+
+    /// Stores the outcome of a search if it belongs to the current
+    /// generation. Returns whether the state changed.
+    pub fn apply(&mut self, message: SearchMessage) -> bool {
+        if message.generation != self.generation {
+            return false;
+        }
+        match message.outcome {
+            Ok(outcome) => {
+                self.files.cache_glob(message.glob, outcome.listing);
+                self.files.replace_files(outcome.files, outcome.skipped);
+                self.results = outcome.results;
+                self.collapsed.clear();
+                self.deselected.clear();
+                self.lines = self.flatten_results();
+                self.last_error = None;
+                self.pane = None;
+            }
+            Err(e) => self.last_error = Some(e),
+        }
+        self.scroll_offset = 0;
+        self.update_pane();
+        true
+    }
+
+    pub fn resize(&mut self, screen: ScreenSize) {
+        self.screen = screen;
+        self.scroll_to(self.scroll_offset);
+    }
+
+    /// Number of result lines that fit on the screen.
+    pub fn view_height(&self) -> usize {
+        self.screen.height.saturating_sub(RESULTS_TOP + 1)
+    }
+
+    /// Results visible at the current scroll offset.
+    pub fn visible_lines(&self) -> &[ResultLine] {
+        let end = (self.scroll_offset + self.view_height()).min(self.lines.len());
+        &self.lines[self.scroll_offset.min(end)..end]
+    }
+
+    /// Offset at which the last line of the results is at the bottom.
+    pub fn max_scroll(&self) -> usize {
+        self.lines.len().saturating_sub(self.view_height())
+    }
+
+    /// Scrolls to `offset`, within the results. Returns whether the view moved.
+    pub fn scroll_to(&mut self, offset: usize) -> bool {
+        let offset = offset.min(self.max_scroll());
+        let moved = offset != self.scroll_offset;
+        self.scroll_offset = offset;
+        self.update_pane();
+        moved
+    }
+
+    pub fn scroll_by(&mut self, lines: isize) -> bool {
+        self.scroll_to(self.scroll_offset.saturating_add_signed(lines))
+    }
+
+    pub fn page_down(&mut self) -> bool {
+        self.scroll_by(self.view_height().max(1) as isize)
+    }
+
+    pub fn page_up(&mut self) -> bool {
+        self.scroll_by(-(self.view_height().max(1) as isize))
+    }
+
+    /// Scrolls to the header of the next file.
+    pub fn next_file(&mut self) -> bool {
+        match (self.scroll_offset + 1..self.lines.len()).find(|i| self.lines[*i].header) {
+            Some(i) => self.scroll_to(i),
+            None => false,
+        }
+    }
+
+    /// Scrolls to the header of the previous file.
+    pub fn previous_file(&mut self) -> bool {
+        match (0..self.scroll_offset)
+            .rev()
+            .find(|i| self.lines[*i].header)
+        {
+            Some(i) => self.scroll_to(i),
+            None => false,
+        }
+    }
+
+    /// Indexes of the matches shown by the line `change` of `file`, none
+    /// for unchanged lines.
+    fn matches_of(&self, file: usize, change: usize) -> Range<usize> {
+        let matched = match self.results.files.get(file) {
+            Some(result) => &result.matched,
+            None => return 0..0,
+        };
+        match matched.binary_search_by_key(&change, |(line, _)| *line) {
+            Ok(i) => matched[i].1.clone(),
+            Err(_) => 0..0,
+        }
+    }
+
+    /// Whether a changed line is written, unless every match it shows was
+    /// unselected.
+    pub fn is_selected(&self, file: usize, change: usize) -> bool {
+        let mut matches = self.matches_of(file, change);
+        matches.is_empty() || matches.any(|found| !self.deselected.contains(&(file, found)))
+    }
+
+    /// Folds or unfolds a file.
+    pub fn toggle_file(&mut self, file: usize) {
+        if !self.collapsed.remove(&file) {
+            self.collapsed.insert(file);
+        }
+        self.lines = self.flatten_results();
+        self.scroll_to(self.scroll_offset);
+    }
+
+    /// Selects or unselects the matches shown by a changed line, on every
+    /// line showing them.
+    pub fn toggle_change(&mut self, file: usize, change: usize) {
+        let selected = self.is_selected(file, change);
+        for found in self.matches_of(file, change) {
+            if selected {
+                self.deselected.insert((file, found));
+            } else {
+                self.deselected.remove(&(file, found));
+            }
+        }
+        self.lines = self.flatten_results();
+    }
+
+    /// Result line drawn at screen row `row`.
+    pub fn line_at(&self, row: usize) -> Option<&ResultLine> {
+        if row < RESULTS_TOP || row >= RESULTS_TOP + self.view_height() {
+            return None;
+        }
+        self.lines.get(self.scroll_offset + row - RESULTS_TOP)
+    }
+
+    /// Scrolls so that the scrollbar caret is at screen row `row`.
+    fn scroll_to_row(&mut self, row: usize) -> bool {
+        let height = self.view_height().max(1);
+        let position = row.saturating_sub(RESULTS_TOP).min(height - 1);
+        self.scroll_to(position * (self.max_scroll() + 1) / height)
+    }
+
+    fn on_scrollbar(&self, column: usize, row: usize) -> bool {
+        column + 1 == self.results_width()
+            && row >= RESULTS_TOP
+            && row < RESULTS_TOP + self.view_height()
+    }
+
+    /// Wheel scrolls the results, a click on a file header folds it, a click
+    /// on a changed line toggles it and the scrollbar can be dragged.
+    /// Returns whether the results need to be drawn again.
+    pub fn handle_mouse(&mut self, event: MouseEvent) -> bool {
+        let (column, row) = (event.column as usize, event.row as usize);
+        match event.kind {
+            MouseEventKind::ScrollDown => self.scroll_by(WHEEL_STEP),
+            MouseEventKind::ScrollUp => self.scroll_by(-WHEEL_STEP),
+            MouseEventKind::Down(MouseButton::Left) if self.on_scrollbar(column, row) => {
+                self.dragging_scrollbar = true;
+                self.scroll_to_row(row);
+                true
+            }
+            MouseEventKind::Down(MouseButton::Left) if column < self.results_width() => {
+                let line = match self.line_at(row) {
+                    Some(line) => line.clone(),
+                    None => return false,
+                };
+                match (line.header, line.change) {
+                    (true, _) => self.toggle_file(line.file),
+                    (_, Some(change)) => self.toggle_change(line.file, change),
+                    _ => return false,
+                }
+                true
+            }
+            MouseEventKind::Drag(MouseButton::Left) if self.dragging_scrollbar => {
+                self.scroll_to_row(row)
+            }
+            MouseEventKind::Up(MouseButton::Left) => {
+                self.dragging_scrollbar = false;
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Decorates every file of the results, with a blank line after each.
+    /// Folded files keep their header and footer, and unselected lines are
+    /// greyed out.
+    fn flatten_results(&self) -> Vec<ResultLine> {
+        let results = &self.results;
+        let mut lines = vec![];
+        for (file, result) in results.files.iter().enumerate() {
+            if result.changes == 0 && results.searched {
+                continue;
+            }
+            let mut decorated = decorate_file_content(
+                result.path.to_string(),
+                result.lines.clone(),
+                &format!("{} matches", result.changes),
+            );
+            let footer = decorated.len() - 1;
+            for (i, text) in decorated.drain(..).enumerate() {
+                let change = match i > 0 && i < footer && results.searched {
+                    true if self.matches_of(file, i - 1).is_empty() => None,
+                    true => Some(i - 1),
+                    false => None,
+                };
+                if self.collapsed.contains(&file) && i > 0 && i < footer {
+                    continue;
+                }
+                let text = match change {
+                    Some(change) if !self.is_selected(file, change) => format!(
+                        "│ {}",
+                        strip_styles(&result.lines[change])
+                            .stylize()
+                            .with(self.palette.muted)
+                            .crossed_out()
+                    ),
+                    _ => text,
+                };
+                lines.push(ResultLine {
+                    text,
+                    header: i == 0,
+                    file,
+                    change,
+                });
+            }
+            lines.push(ResultLine {
+                text: String::new(),
+                header: false,
+                file,
+                change: None,
+            });
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+use super::search::FileChanges;
+
+#[cfg(test)]
+fn message(generation: usize, lines: usize) -> SearchMessage {
+    use super::search::SearchOutcome;
+
+    SearchMessage {
+        generation,
+        glob: "*".to_string(),
+        outcome: Ok(SearchOutcome {
+            listing: vec![],
+            files: vec![],
+            skipped: 0,
+            results: SearchResults {
+                files: vec![FileChanges {
+                    lines: vec![String::new(); lines],
+                    ..FileChanges::default()
+                }],
+                ..SearchResults::default()
+            },
+        }),
+    }
+}
+
+#[test]
+fn skip_unchanged_tokens() {
+    use super::split_query::split_query;
+
+    let mut state = AppState::default();
+    assert!(state.needs_search(&split_query("* search")));
+    assert!(!state.needs_search(&split_query("*  'search'")));
+    assert!(state.needs_search(&split_query("* search replace")));
+    assert!(state.needs_search(&split_query("* search replace -i")));
+    assert!(!state.needs_search(&split_query("* search replace -i \"")));
+}
+
+#[test]
+fn ignore_superseded_generations() {
+    let mut state = AppState::default();
+    let first = state.next_generation();
+    let second = state.next_generation();
+    assert!(!state.apply(message(first, 3)));
+    assert_eq!(state.lines.len(), 0);
+    assert!(state.apply(message(second, 5)));
+    assert_eq!(state.results.files[0].lines.len(), 5);
+}
+
+#[test]
+fn scroll_within_results() {
+    let screen = ScreenSize {
+        width: 80,
+        height: RESULTS_TOP + 11,
+    };
+    let mut state = AppState::new("", SearchOptions::default(), screen);
+    let generation = state.next_generation();
+    state.apply(message(generation, 25));
+    // A header, 25 lines, a footer and a blank line.
+    assert_eq!(state.lines.len(), 28);
+    assert_eq!(state.view_height(), 10);
+    assert!(!state.scroll_by(-1));
+    assert!(state.scroll_by(1));
+    assert!(state.page_down());
+    assert_eq!(state.scroll_offset, 11);
+    assert!(state.page_down());
+    assert!(!state.page_down());
+    assert_eq!(state.scroll_offset, 18);
+    assert_eq!(state.visible_lines().len(), 10);
+    assert!(state.page_up());
+    assert_eq!(state.scroll_offset, 8);
+    assert!(state.scroll_to(0));
+    assert!(state.scroll_to(usize::MAX));
+    assert_eq!(state.scroll_offset, 18);
+
+    state.resize(ScreenSize {
+        width: 80,
+        height: RESULTS_TOP + 21,
+    });
+    assert_eq!(state.scroll_offset, 8);
+}
+
+#[test]
+fn jump_between_files() {
+    let screen = ScreenSize {
+        width: 80,
+        height: RESULTS_TOP + 3,
+    };
+    let mut state = AppState::new("", SearchOptions::default(), screen);
+    let generation = state.next_generation();
+    let mut second = message(generation, 2);
+    if let Ok(outcome) = &mut second.outcome {
+        let file = outcome.results.files[0].clone();
+        outcome.results.files.push(file);
+    }
+    state.apply(second);
+    assert!(!state.previous_file());
+    assert!(state.next_file());
+    assert_eq!(state.scroll_offset, 5);
+    assert!(state.lines[5].header);
+    assert!(!state.next_file());
+    assert!(state.scroll_by(-2));
+    assert!(state.previous_file());
+    assert_eq!(state.scroll_offset, 0);
+}
+
+#[cfg(test)]
+fn mouse(kind: MouseEventKind, column: u16, row: usize) -> MouseEvent {
+    MouseEvent {
+        kind,
+        column,
+        row: row as u16,
+        modifiers: crossterm::event::KeyModifiers::NONE,
+    }
 }
 
-pub async fn store_file(key: String, value: String) {
-    let mut state = STATE.lock().await;
-    state.insert(format!("file_{}", key), value);
+#[test]
+fn click_to_fold_and_toggle() {
+    use super::decorate_file_content::skipped_line;
+
+    let screen = ScreenSize {
+        width: 80,
+        height: RESULTS_TOP + 11,
+    };
+    let mut state = AppState::new("", SearchOptions::default(), screen);
+    let generation = state.next_generation();
+    let mut changes = message(generation, 0);
+    if let Ok(outcome) = &mut changes.outcome {
+        outcome.results.searched = true;
+        outcome.results.files[0].changes = 2;
+        outcome.results.files[0].lines =
+            vec!["   1 a".to_string(), skipped_line(), "   5 b".to_string()];
+        outcome.results.files[0].matched = vec![(0, 0..1), (2, 1..2)];
+    }
+    state.apply(changes);
+    let left = MouseEventKind::Down(MouseButton::Left);
+
+    assert!(state.handle_mouse(mouse(left, 4, RESULTS_TOP + 1)));
+    assert!(!state.is_selected(0, 0));
+    assert!(state.is_selected(0, 2));
+    assert_eq!(strip_styles(&state.lines[1].text), "│    1 a");
+    assert!(!state.handle_mouse(mouse(left, 4, RESULTS_TOP + 2)));
+
+    assert!(state.handle_mouse(mouse(left, 4, RESULTS_TOP)));
+    assert_eq!(state.lines.len(), 3);
+    assert!(state.handle_mouse(mouse(left, 4, RESULTS_TOP)));
+    assert_eq!(state.lines.len(), 6);
+    assert!(!state.is_selected(0, 0));
 }
 
-pub async fn get_file(key: &str) -> Option<String> {
-    let state = STATE.lock().await;
-    state.get(&format!("file_{}", key)).cloned()
+#[test]
+fn skip_context_lines() {
+    let screen = ScreenSize {
+        width: 80,
+        height: RESULTS_TOP + 11,
+    };
+    let options = SearchOptions {
+        context: 1,
+        ..SearchOptions::default()
+    };
+    let (preview, changes) =
+        Replacer::new("a", "x")
+            .options(options)
+            .render("b\na\nc\n", true, None);
+    assert_eq!(preview.matched, vec![(1, 0..1)]);
+
+    let mut state = AppState::new("", SearchOptions::default(), screen);
+    let generation = state.next_generation();
+    let mut outcome = message(generation, 0);
+    if let Ok(outcome) = &mut outcome.outcome {
+        outcome.results.searched = true;
+        outcome.results.replacing = true;
+        outcome.results.files[0] = FileChanges {
+            lines: preview.lines,
+            matched: preview.matched,
+            changes,
+            ..FileChanges::default()
+        };
+    }
+    state.apply(outcome);
+    let changes = state.lines.iter().filter_map(|line| line.change);
+    assert_eq!(changes.collect::<Vec<usize>>(), vec![1]);
+    assert_eq!(state.selection(), Some((0, 2)));
+
+    let left = MouseEventKind::Down(MouseButton::Left);
+    assert!(!state.handle_mouse(mouse(left, 4, RESULTS_TOP + 1)));
+    assert!(state.perform(Action::ToggleMatch));
+    assert!(state.is_selected(0, 0));
+    assert!(!state.is_selected(0, 1));
 }
 
-pub async fn get_files_names() -> Vec<String> {
-    let state = STATE.lock().await;
-    state
-        .keys()
-        .filter(|x| x.starts_with("file_"))
-        .cloned()
-        .map(|x| x.replace("file_", ""))
-        .collect()
+#[test]
+fn cancel_running_search() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let _entered = runtime.enter();
+    let mut state = AppState::default();
+    state.start_search(tokio::spawn(std::future::pending()));
+    assert!(state.searching());
+    state.cancel_search();
+    assert!(!state.searching());
 }
 
-pub async fn clear_files() {
-    let mut state = STATE.lock().await;
-    state.clear();
+#[test]
+fn wheel_and_drag_scrollbar() {
+    let screen = ScreenSize {
+        width: 80,
+        height: RESULTS_TOP + 11,
+    };
+    let mut state = AppState::new("", SearchOptions::default(), screen);
+    let generation = state.next_generation();
+    state.apply(message(generation, 40));
+    assert_eq!(state.max_scroll(), 33);
+
+    assert!(state.handle_mouse(mouse(MouseEventKind::ScrollDown, 4, 0)));
+    assert_eq!(state.scroll_offset, 3);
+    assert!(state.handle_mouse(mouse(MouseEventKind::ScrollUp, 4, 0)));
+    assert_eq!(state.scroll_offset, 0);
+
+    let left = MouseButton::Left;
+    assert!(state.handle_mouse(mouse(MouseEventKind::Down(left), 79, RESULTS_TOP + 9)));
+    assert_eq!(state.scroll_offset, 30);
+    assert!(state.handle_mouse(mouse(MouseEventKind::Drag(left), 60, 0)));
+    assert_eq!(state.scroll_offset, 0);
+    state.handle_mouse(mouse(MouseEventKind::Up(left), 60, 0));
+    assert!(!state.handle_mouse(mouse(MouseEventKind::Drag(left), 79, RESULTS_TOP + 5)));
 }
 
-pub async fn store_key_value(key: String, value: String) {
-    let mut state = STATE.lock().await;
-    state.insert(key, value);
+#[test]
+fn recall_and_search_history() {
+    let key = |code: KeyCode| KeyEvent::new(code, KeyModifiers::CONTROL);
+    let mut state = AppState::new(
+        "src/* typed",
+        SearchOptions::default(),
+        ScreenSize::default(),
+    );
+    state.prompt.compact = true;
+    state.prompt.set_query("src/* typed");
+    state.history.push("src/* one", false).unwrap();
+    state.history.push("lib/* two", true).unwrap();
+
+    assert!(state.perform(Action::HistoryPrevious));
+    assert!(state.perform(Action::HistoryPrevious));
+    assert_eq!(state.prompt.query(), "src/* one");
+    assert!(state.perform(Action::HistoryNext));
+    assert!(state.perform(Action::HistoryNext));
+    assert_eq!(state.prompt.query(), "src/* typed");
+
+    assert!(!state.handle_history_search_key(key(KeyCode::Char('r'))));
+    assert!(state.perform(Action::HistorySearch));
+    for char in "/*".chars() {
+        state.handle_history_search_key(KeyEvent::new(KeyCode::Char(char), KeyModifiers::NONE));
+    }
+    assert_eq!(state.history_search.as_ref().unwrap().found, Some(1));
+    assert!(state.handle_history_search_key(key(KeyCode::Char('r'))));
+    assert_eq!(state.history_search.as_ref().unwrap().found, Some(0));
+    assert!(!state.handle_history_search_key(key(KeyCode::Char('c'))));
+    assert!(state.handle_history_search_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE)));
+    assert_eq!(state.prompt.query(), "src/* one");
+    assert!(state.history_search.is_none());
+    assert!(!state.handle_history_search_key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE)));
 }
 
-pub async fn get_key_value(key: &str) -> Option<String> {
-    let state = STATE.lock().await;
-    state.get(key).cloned()
+#[test]
+fn write_selected_changes_and_undo() {
+    use super::split_query::split_query;
+    use super::temp_dir::TempDir;
+
+    let dir = TempDir::new("write-selected-changes");
+    let path = dir.file("a.txt");
+    std::fs::write(&path, "a\nb\na\n").unwrap();
+    let replacer = Replacer::new("a", "x");
+    let (preview, changes) = replacer.render("a\nb\na\n", true, None);
+
+    let mut state = AppState::default();
+    assert!(state.needs_search(&split_query(&format!("{} a x", path))));
+    let generation = state.next_generation();
+    let mut outcome = message(generation, 0);
+    if let Ok(outcome) = &mut outcome.outcome {
+        outcome.results.searched = true;
+        outcome.results.replacing = true;
+        outcome.results.files[0] = FileChanges {
+            path: path.clone(),
+            lines: preview.lines,
+            matched: preview.matched,
+            changes,
+            bytes: 6,
+        };
+    }
+    state.apply(outcome);
+    state.toggle_change(0, 2);
+
+    assert_eq!(state.write_changes().unwrap(), 1);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "x\nb\na\n");
+    assert!(state.needs_search(&split_query(&format!("{} a x", path))));
+    assert!(state.history.entries()[0].written);
+    state.remember_search(true);
+    assert_eq!(state.history.entries().len(), 1);
+    assert!(state.perform(Action::Undo));
+    assert_eq!(state.notice.as_deref(), Some("1 files restored"));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\nb\na\n");
+    assert_eq!(state.undo().unwrap(), 0);
+}
+
+#[test]
+fn preview_and_cancel_themes() {
+    use super::syntax_highlight::DEFAULT_THEME;
+
+    let key = |code: KeyCode| KeyEvent::new(code, KeyModifiers::NONE);
+    let mut state = AppState::new("src/* a", SearchOptions::default(), ScreenSize::default());
+    state.options.theme = Some(DEFAULT_THEME.to_string());
+    assert!(!state.handle_theme_picker_key(key(KeyCode::Down)));
+    assert!(state.perform(Action::PickTheme));
+
+    assert!(state.handle_theme_picker_key(key(KeyCode::Up)));
+    let previewed = state.options.theme.clone().unwrap();
+    assert_ne!(previewed, DEFAULT_THEME);
+    assert!(state.needs_search(&state.split()));
+    assert!(state.handle_theme_picker_key(key(KeyCode::Char('x'))));
+    assert!(state.handle_theme_picker_key(key(KeyCode::Esc)));
+    assert!(state.theme_picker.is_none());
+    assert_eq!(state.options.theme.as_deref(), Some(DEFAULT_THEME));
+
+    state.perform(Action::PickTheme);
+    state.handle_theme_picker_key(key(KeyCode::Up));
+    assert!(state.handle_theme_picker_key(key(KeyCode::Enter)));
+    assert_eq!(state.options.theme, Some(previewed));
+}
+
+#[test]
+fn deselect_two_line_changes() {
+    use super::split_query::split_query;
+    use super::temp_dir::TempDir;
+
+    let dir = TempDir::new("deselect-two-line-changes");
+    let path = dir.file("a.txt");
+    std::fs::write(&path, "a\nb\na\nb\nc\n").unwrap();
+    let options = SearchOptions {
+        diff: DiffStyle::TwoLine,
+        ..SearchOptions::default()
+    };
+    let (preview, changes) =
+        Replacer::new("a\nb", "x")
+            .options(options.clone())
+            .render("a\nb\na\nb\nc\n", true, None);
+
+    let mut state = AppState::new("", options, ScreenSize::default());
+    assert!(state.needs_search(&split_query(&format!("{} 'a\\nb' x", path))));
+    let generation = state.next_generation();
+    let mut outcome = message(generation, 0);
+    if let Ok(outcome) = &mut outcome.outcome {
+        outcome.results.searched = true;
+        outcome.results.replacing = true;
+        outcome.results.files[0] = FileChanges {
+            path: path.clone(),
+            lines: preview.lines,
+            matched: preview.matched,
+            changes,
+            ..FileChanges::default()
+        };
+    }
+    state.apply(outcome);
+    let plain = state
+        .lines
+        .iter()
+        .map(|line| strip_styles(&line.text).split_whitespace().collect())
+        .collect::<Vec<String>>();
+    assert_eq!(
+        plain[1..7],
+        ["│1-a", "│2-b", "│1+x", "│3-a", "│4-b", "│3+x"]
+    );
+    let changes = state.lines.iter().filter_map(|line| line.change);
+    assert_eq!(changes.collect::<Vec<usize>>(), vec![0, 1, 3, 4]);
+
+    state.toggle_change(0, 1);
+    assert!(!state.is_selected(0, 0));
+    assert!(!state.is_selected(0, 1));
+    assert!(state.is_selected(0, 3));
+    state.toggle_change(0, 0);
+    assert!(state.is_selected(0, 1));
+    state.toggle_change(0, 4);
+    assert!(!state.is_selected(0, 3));
+    assert_eq!(state.write_changes().unwrap(), 1);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "x\na\nb\nc\n");
 }
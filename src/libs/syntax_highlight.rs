@@ -1,31 +1,318 @@
-use std::io::BufRead;
-use syntect::easy::HighlightFile;
-use syntect::highlighting::{Style, ThemeSet};
-use syntect::parsing::SyntaxSet;
-use syntect::util::as_24_bit_terminal_escaped;
-
-pub fn highlight_file(path: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let ss = SyntaxSet::load_defaults_newlines();
-    let mut ts = ThemeSet::load_defaults();
-    ts.add_from_folder("assets/themes")?;
-
-    let mut highlighter = HighlightFile::new(
-        path,
-        &ss,
-        &ts.themes["Nord"],
-    )?;
-
-    let mut line = String::new();
-    let mut result = String::new();
-    while highlighter.reader.read_line(&mut line)? > 0 {
-        {
-            let regions: Vec<(Style, &str)> = highlighter
-                .highlight_lines
-                .highlight_line(&line, &ss)?;
-
-            result.push_str( &format!("{}", as_24_bit_terminal_escaped(&regions[..], true)));
-        }
-        line.clear();
-    }
-    Ok(result)
+use std::io::Cursor;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crossterm::style::Color;
+use syntect::highlighting::{
+    HighlightIterator, HighlightState, Highlighter, Style, Theme, ThemeSet,
+};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+
+use super::config::Config;
+use super::theme::ColorDepth;
+
+/// Theme of the previews unless the configuration names another one.
+pub const DEFAULT_THEME: &str = "Nord";
+
+const NORD_THEME: &[u8] = include_bytes!("../../assets/themes/Nord.tmTheme");
+/// Background of the matches and substitutes of themes without a selection
+/// colour.
+const MARK_BACKGROUND: Color = Color::Rgb {
+    r: 0x3b,
+    g: 0x4a,
+    b: 0x3a,
+};
+/// Most bytes parsed to carry the state of a file to one of its lines.
+/// Lines further in are coloured as if the file started there, so that
+/// large files are previewed without parsing them whole.
+const MAX_PARSED_BYTES: usize = 1024 * 1024;
+/// Background of the text removed by replacements.
+const REMOVED_BACKGROUND: Color = Color::Rgb {
+    r: 0x4c,
+    g: 0x2b,
+    b: 0x31,
+};
+
+/// Syntax definitions, loaded on first use and shared by every search.
+pub fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// Themes of syntect with the bundled Nord theme and the `.tmTheme` files
+/// of the `themes` directory next to the user configuration, loaded on first
+/// use.
+pub fn theme_set() -> &'static ThemeSet {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    THEMES.get_or_init(|| {
+        let mut themes = ThemeSet::load_defaults();
+        if let Ok(nord) = ThemeSet::load_from_reader(&mut Cursor::new(NORD_THEME)) {
+            themes.themes.insert(DEFAULT_THEME.to_string(), nord);
+        }
+        let user_themes = Config::user_path()
+            .and_then(|path| Some(path.parent()?.join("themes")))
+            .filter(|dir| dir.is_dir())
+            .and_then(|dir| ThemeSet::load_from_folder(dir).ok());
+        if let Some(user_themes) = user_themes {
+            themes.themes.extend(user_themes.themes);
+        }
+        themes
+    })
+}
+
+/// Names of the themes, sorted.
+pub fn theme_names() -> Vec<String> {
+    theme_set().themes.keys().cloned().collect()
+}
+
+/// Colours the lines of a file by the syntax of its type.
+#[derive(Debug, Clone, Copy)]
+pub struct SyntaxHighlighter {
+    syntax: &'static SyntaxReference,
+    theme: &'static Theme,
+    depth: ColorDepth,
+}
+
+impl SyntaxHighlighter {
+    /// Highlighter of the file at `path` with `content`, found by extension
+    /// then by first line. Plain text and unknown themes are not highlighted.
+    pub fn for_file(path: &str, content: &str, theme: &str) -> Option<SyntaxHighlighter> {
+        let syntaxes = syntax_set();
+        let theme = theme_set().themes.get(theme)?;
+        let extension = Path::new(path).extension().and_then(|e| e.to_str());
+        let syntax = extension
+            .and_then(|extension| syntaxes.find_syntax_by_extension(extension))
+            .or_else(|| syntaxes.find_syntax_by_first_line(content.lines().next()?))?;
+        if syntax.name == syntaxes.find_syntax_plain_text().name {
+            return None;
+        }
+        Some(SyntaxHighlighter {
+            syntax,
+            theme,
+            depth: ColorDepth::default(),
+        })
+    }
+
+    /// Converts the colours for a terminal of `depth`.
+    pub fn depth(mut self, depth: ColorDepth) -> SyntaxHighlighter {
+        self.depth = depth;
+        self
+    }
+
+    /// Background of the matches and substitutes: the selection colour of
+    /// the theme, so that the colours of the syntax stay legible over it.
+    pub fn mark_background(&self) -> Color {
+        let background = match self.theme.settings.selection {
+            Some(color) => Color::Rgb {
+                r: color.r,
+                g: color.g,
+                b: color.b,
+            },
+            None => MARK_BACKGROUND,
+        };
+        self.depth.convert(background)
+    }
+
+    /// Background of the text removed by replacements.
+    pub fn removed_background(&self) -> Color {
+        self.depth.convert(REMOVED_BACKGROUND)
+    }
+
+    /// Foreground colour of the byte ranges of `text`, parsed from its first
+    /// line, line breaks left out. Lines that fail to parse are left plain.
+    pub fn colors(&self, text: &str) -> Vec<(Range<usize>, Color)> {
+        self.state().colors(text)
+    }
+
+    /// Parse state at the start of a file.
+    pub fn state(&self) -> LineState {
+        let highlighter = Highlighter::new(self.theme);
+        LineState {
+            syntax: *self,
+            parse: ParseState::new(self.syntax),
+            highlight: HighlightState::new(&highlighter, ScopeStack::new()),
+            offset: 0,
+        }
+    }
+}
+
+/// Parse state of a file at the start of one of its lines, so that the text
+/// from that line is coloured as it is when the file is read from its start.
+#[derive(Debug, Clone)]
+pub struct LineState {
+    syntax: SyntaxHighlighter,
+    parse: ParseState,
+    highlight: HighlightState,
+    /// Offset of the line in the file.
+    offset: usize,
+}
+
+impl LineState {
+    /// Parses the lines of `content` up to `offset`, the start of a line
+    /// after the current one. Past `MAX_PARSED_BYTES`, starts again from
+    /// `offset` instead.
+    pub fn advance(&mut self, content: &str, offset: usize) {
+        if offset > MAX_PARSED_BYTES {
+            *self = LineState {
+                offset,
+                ..self.syntax.state()
+            };
+            return;
+        }
+        let highlighter = Highlighter::new(self.syntax.theme);
+        for line in content[self.offset..offset].split_terminator('\n') {
+            self.highlight_line(&highlighter, line);
+        }
+        self.offset = offset;
+    }
+
+    /// Foreground colour of the byte ranges of `text`, which starts at the
+    /// current line, line breaks left out. Lines that fail to parse are
+    /// left plain.
+    pub fn colors(&self, text: &str) -> Vec<(Range<usize>, Color)> {
+        let highlighter = Highlighter::new(self.syntax.theme);
+        let mut state = self.clone();
+        let mut colors = vec![];
+        let mut start = 0;
+        for line in text.split('\n') {
+            let content = line.trim_end_matches('\r');
+            let mut position = start;
+            for (style, region) in state.highlight_line(&highlighter, content) {
+                let end = (position + region).min(start + content.len());
+                if end > position {
+                    let color = style.foreground;
+                    let color = Color::Rgb {
+                        r: color.r,
+                        g: color.g,
+                        b: color.b,
+                    };
+                    colors.push((position..end, self.syntax.depth.convert(color)));
+                }
+                position = end;
+            }
+            start += line.len() + 1;
+        }
+        colors
+    }
+
+    /// Styles and lengths of the regions of `line`, parsed with its line
+    /// break restored.
+    fn highlight_line(&mut self, highlighter: &Highlighter, line: &str) -> Vec<(Style, usize)> {
+        let terminated = format!("{}\n", line.trim_end_matches('\r'));
+        let ops = match self.parse.parse_line(&terminated, syntax_set()) {
+            Ok(ops) => ops,
+            Err(_e) => return vec![],
+        };
+        HighlightIterator::new(&mut self.highlight, &ops, &terminated, highlighter)
+            .map(|(style, region)| (style, region.len()))
+            .collect()
+    }
+}
+
+/// Choice of the syntax theme in the interactive mode. The theme selected
+/// is previewed until it is kept or the choice cancelled.
+#[derive(Debug, Clone)]
+pub struct ThemePicker {
+    pub names: Vec<String>,
+    pub selected: usize,
+    /// Theme before the choice, restored when it is cancelled.
+    pub original: Option<String>,
+}
+
+impl ThemePicker {
+    /// Lists the themes, starting from `current`.
+    pub fn new(current: Option<&str>) -> ThemePicker {
+        let names = theme_names();
+        let selected = names
+            .iter()
+            .position(|name| Some(name.as_str()) == current)
+            .unwrap_or(0);
+        ThemePicker {
+            names,
+            selected,
+            original: current.map(|name| name.to_string()),
+        }
+    }
+
+    pub fn selected(&self) -> &str {
+        &self.names[self.selected]
+    }
+
+    /// Moves the selection by `rows`, within the list. Returns whether it
+    /// moved.
+    pub fn select_by(&mut self, rows: isize) -> bool {
+        let selected = self
+            .selected
+            .saturating_add_signed(rows)
+            .min(self.names.len().saturating_sub(1));
+        let moved = selected != self.selected;
+        self.selected = selected;
+        moved
+    }
+}
+
+#[test]
+fn color_lines_by_file_type() {
+    let highlighter = SyntaxHighlighter::for_file("src/main.rs", "", DEFAULT_THEME).unwrap();
+    let text = "fn main() {\r\n    let x = \"a\";\n}";
+    let colors = highlighter.colors(text);
+    assert_eq!(colors.first().map(|(range, _)| range.clone()), Some(0..2));
+    assert!(colors
+        .iter()
+        .all(|(range, _)| !text[range.clone()].contains(['\r', '\n'])));
+    let keyword = colors[0].1;
+    let string = colors
+        .iter()
+        .find(|(range, _)| &text[range.clone()] == "a")
+        .map(|(_, color)| *color);
+    assert!(string.is_some_and(|string| string != keyword));
+
+    let script = SyntaxHighlighter::for_file("run", "#!/bin/sh\necho", DEFAULT_THEME);
+    assert!(script.is_some());
+    assert!(SyntaxHighlighter::for_file("notes.txt", "", DEFAULT_THEME).is_none());
+    assert!(SyntaxHighlighter::for_file("a.rs", "", "No such theme").is_none());
+}
+
+#[test]
+fn parse_large_files_from_the_changed_lines() {
+    let highlighter = SyntaxHighlighter::for_file("a.rs", "", DEFAULT_THEME).unwrap();
+    let content = format!("/*\n{}fn a() {{}}\n", "x\n".repeat(MAX_PARSED_BYTES / 2));
+    let offset = content.len() - "fn a() {}\n".len();
+    let mut state = highlighter.state();
+    state.advance(&content, offset);
+    assert_eq!(state.offset, offset);
+    assert_eq!(state.colors("fn a() {}"), highlighter.colors("fn a() {}"));
+
+    let content = "/*\nx\n";
+    let mut state = highlighter.state();
+    state.advance(content, content.len());
+    assert_ne!(state.colors("fn a() {}"), highlighter.colors("fn a() {}"));
+}
+
+#[test]
+fn convert_colors_and_pick_themes() {
+    let highlighter = SyntaxHighlighter::for_file("a.rs", "", DEFAULT_THEME).unwrap();
+    let nord_selection = Color::Rgb {
+        r: 0x43,
+        g: 0x4c,
+        b: 0x5e,
+    };
+    assert_eq!(highlighter.mark_background(), nord_selection);
+    let highlighter = highlighter.depth(ColorDepth::Ansi256);
+    assert_eq!(highlighter.mark_background(), Color::AnsiValue(239));
+    assert!(highlighter
+        .colors("fn main() {}")
+        .iter()
+        .all(|(_, color)| matches!(color, Color::AnsiValue(_))));
+
+    let mut picker = ThemePicker::new(Some(DEFAULT_THEME));
+    assert_eq!(picker.selected(), DEFAULT_THEME);
+    assert!(picker.names.contains(&"base16-ocean.dark".to_string()));
+    assert!(picker.select_by(-100));
+    assert_eq!(picker.selected, 0);
+    assert!(!picker.select_by(-1));
+    assert!(picker.select_by(100));
+    assert_eq!(picker.selected, picker.names.len() - 1);
+    assert_eq!(picker.original.as_deref(), Some(DEFAULT_THEME));
 }
@@ -0,0 +1,342 @@
+use std::str::FromStr;
+
+use crossterm::style::Color;
+
+/// When output is coloured, as chosen by `--color`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum ColorMode {
+    /// Coloured on a terminal, unless `NO_COLOR` is set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<ColorMode, String> {
+        match mode {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err(format!(
+                "invalid colour mode {}, expected auto, always or never",
+                mode
+            )),
+        }
+    }
+}
+
+impl ColorMode {
+    /// Whether output to a `terminal` or not is coloured, following
+    /// <https://no-color.org>: a non-empty `NO_COLOR` turns colours off
+    /// unless they are asked for.
+    pub fn colored(self, terminal: bool) -> bool {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        self.colored_with(terminal, no_color)
+    }
+
+    fn colored_with(self, terminal: bool, no_color: bool) -> bool {
+        match self {
+            ColorMode::Auto => terminal && !no_color,
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+}
+
+/// Colours a terminal can show.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum ColorDepth {
+    /// Any 24-bit colour.
+    #[default]
+    TrueColor,
+    /// The 256 colours of xterm.
+    Ansi256,
+    /// The 16 named colours.
+    Ansi16,
+}
+
+/// Colours of the 16 named colours in xterm, by ANSI number.
+const ANSI_16: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::DarkRed, (205, 0, 0)),
+    (Color::DarkGreen, (0, 205, 0)),
+    (Color::DarkYellow, (205, 205, 0)),
+    (Color::DarkBlue, (0, 0, 238)),
+    (Color::DarkMagenta, (205, 0, 205)),
+    (Color::DarkCyan, (0, 205, 205)),
+    (Color::Grey, (229, 229, 229)),
+    (Color::DarkGrey, (127, 127, 127)),
+    (Color::Red, (255, 0, 0)),
+    (Color::Green, (0, 255, 0)),
+    (Color::Yellow, (255, 255, 0)),
+    (Color::Blue, (92, 92, 255)),
+    (Color::Magenta, (255, 0, 255)),
+    (Color::Cyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// Levels of each channel in the 6×6×6 colour cube of the 256 colours.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+impl ColorDepth {
+    /// Depth of the terminal, told by `$COLORTERM` then `$TERM`.
+    pub fn detect() -> ColorDepth {
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+        ColorDepth::from_env(&colorterm, &term)
+    }
+
+    fn from_env(colorterm: &str, term: &str) -> ColorDepth {
+        match (colorterm, term) {
+            ("truecolor" | "24bit", _) => ColorDepth::TrueColor,
+            (_, term) if term.ends_with("-direct") => ColorDepth::TrueColor,
+            (_, term) if term.contains("256") => ColorDepth::Ansi256,
+            _ => ColorDepth::Ansi16,
+        }
+    }
+
+    /// The colour closest to `color` the terminal can show.
+    pub fn convert(self, color: Color) -> Color {
+        match (self, color) {
+            (ColorDepth::Ansi256, Color::Rgb { r, g, b }) => Color::AnsiValue(ansi_256(r, g, b)),
+            (ColorDepth::Ansi16, Color::Rgb { r, g, b }) => ansi_16(r, g, b),
+            (ColorDepth::Ansi16, Color::AnsiValue(n)) => {
+                let (r, g, b) = ansi_rgb(n);
+                ansi_16(r, g, b)
+            }
+            _ => color,
+        }
+    }
+}
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let channel = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    channel(r1, r2) + channel(g1, g2) + channel(b1, b2)
+}
+
+/// RGB of the colour numbered `n` of the 256 colours.
+fn ansi_rgb(n: u8) -> (u8, u8, u8) {
+    match n {
+        0..=15 => ANSI_16[n as usize].1,
+        16..=231 => {
+            let n = n - 16;
+            let level = |i: u8| CUBE_LEVELS[i as usize];
+            (level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        _ => {
+            let grey = 8 + (n - 232) * 10;
+            (grey, grey, grey)
+        }
+    }
+}
+
+/// Number of the colour of the cube or of the grey ramp nearest to an RGB
+/// colour.
+fn ansi_256(r: u8, g: u8, b: u8) -> u8 {
+    let level = |v: u8| match v {
+        0..=47 => 0,
+        48..=114 => 1,
+        _ => (v - 35) / 40,
+    };
+    let cube = 16 + 36 * level(r) + 6 * level(g) + level(b);
+    let average = ((r as u16 + g as u16 + b as u16) / 3) as u8;
+    let grey = 232 + (average.saturating_sub(3) / 10).min(23);
+    match distance((r, g, b), ansi_rgb(grey)) < distance((r, g, b), ansi_rgb(cube)) {
+        true => grey,
+        false => cube,
+    }
+}
+
+fn ansi_16(r: u8, g: u8, b: u8) -> Color {
+    ANSI_16
+        .iter()
+        .min_by_key(|(_, rgb)| distance((r, g, b), *rgb))
+        .map(|(color, _)| *color)
+        .unwrap_or(Color::Reset)
+}
+
+/// SGR parameter of the colour of the 16 closest to `color`, as a
+/// foreground (30–37, 90–97) or a background (40–47, 100–107), or of the
+/// default colour for [`Color::Reset`].
+pub fn ansi_16_sgr(color: Color, background: bool) -> u8 {
+    let base = match background {
+        true => 40,
+        false => 30,
+    };
+    let color = ColorDepth::Ansi16.convert(color);
+    match ANSI_16.iter().position(|(named, _)| *named == color) {
+        Some(i) if i < 8 => base + i as u8,
+        Some(i) => base + 60 + (i - 8) as u8,
+        None => base + 9,
+    }
+}
+
+/// Reads a colour named as in crossterm (`blue`, `dark_grey`), numbered in
+/// the 256 colours (`208`) or in hexadecimal (`#88c0d0`).
+pub fn parse_color(name: &str) -> Result<Color, String> {
+    let invalid = || format!("invalid colour {}", name);
+    if let Some(hex) = name.strip_prefix('#') {
+        if hex.len() != 6 {
+            return Err(invalid());
+        }
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2).unwrap_or(""), 16);
+        return match (channel(0), channel(2), channel(4)) {
+            (Ok(r), Ok(g), Ok(b)) => Ok(Color::Rgb { r, g, b }),
+            _ => Err(invalid()),
+        };
+    }
+    if let Ok(n) = name.parse::<u8>() {
+        return Ok(Color::AnsiValue(n));
+    }
+    Color::try_from(name).map_err(|_| invalid())
+}
+
+/// Colours of the interface, set by the `[colors]` table of the
+/// configuration.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Palette {
+    pub glob: Color,
+    pub search: Color,
+    pub replace: Color,
+    /// Modifiers of the query and search modes of the status bar.
+    pub modifier: Color,
+    pub error: Color,
+    /// Line numbers, labels and details.
+    pub muted: Color,
+    /// Outcome of writes and undos.
+    pub notice: Color,
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette {
+            glob: Color::Blue,
+            search: Color::Yellow,
+            replace: Color::Green,
+            modifier: Color::Magenta,
+            error: Color::Red,
+            muted: Color::DarkGrey,
+            notice: Color::Cyan,
+        }
+    }
+}
+
+impl Palette {
+    /// Elements named in the `[colors]` table.
+    pub const ELEMENTS: [&'static str; 7] = [
+        "glob", "search", "replace", "modifier", "error", "muted", "notice",
+    ];
+
+    fn element_mut(&mut self, element: &str) -> Option<&mut Color> {
+        match element {
+            "glob" => Some(&mut self.glob),
+            "search" => Some(&mut self.search),
+            "replace" => Some(&mut self.replace),
+            "modifier" => Some(&mut self.modifier),
+            "error" => Some(&mut self.error),
+            "muted" => Some(&mut self.muted),
+            "notice" => Some(&mut self.notice),
+            _ => None,
+        }
+    }
+
+    /// Sets the colour of `element`, parsed by [`parse_color`].
+    pub fn set(&mut self, element: &str, color: &str) -> Result<(), String> {
+        let color = parse_color(color).map_err(|e| format!("{} for {}", e, element))?;
+        let slot = self.element_mut(element).ok_or_else(|| {
+            format!(
+                "unknown colour element {}, expected one of {}",
+                element,
+                Palette::ELEMENTS.join(", ")
+            )
+        })?;
+        *slot = color;
+        Ok(())
+    }
+
+    /// The palette as close as a terminal of `depth` shows it.
+    pub fn convert(mut self, depth: ColorDepth) -> Palette {
+        for element in Palette::ELEMENTS {
+            if let Some(color) = self.element_mut(element) {
+                *color = depth.convert(*color);
+            }
+        }
+        self
+    }
+}
+
+#[test]
+fn detect_and_downgrade_colors() {
+    assert_eq!(
+        ColorDepth::from_env("truecolor", "xterm"),
+        ColorDepth::TrueColor
+    );
+    assert_eq!(
+        ColorDepth::from_env("", "xterm-256color"),
+        ColorDepth::Ansi256
+    );
+    assert_eq!(ColorDepth::from_env("", "linux"), ColorDepth::Ansi16);
+
+    let nord_blue = Color::Rgb {
+        r: 0x88,
+        g: 0xc0,
+        b: 0xd0,
+    };
+    assert_eq!(ColorDepth::TrueColor.convert(nord_blue), nord_blue);
+    assert_eq!(
+        ColorDepth::Ansi256.convert(nord_blue),
+        Color::AnsiValue(110)
+    );
+    let grey = Color::Rgb {
+        r: 0x43,
+        g: 0x4c,
+        b: 0x5e,
+    };
+    assert_eq!(ColorDepth::Ansi256.convert(grey), Color::AnsiValue(239));
+    assert_eq!(ColorDepth::Ansi16.convert(grey), Color::DarkGrey);
+    assert_eq!(ColorDepth::Ansi16.convert(nord_blue), Color::Grey);
+    assert_eq!(
+        ColorDepth::Ansi16.convert(Color::AnsiValue(196)),
+        Color::Red
+    );
+    assert_eq!(ColorDepth::Ansi16.convert(Color::Blue), Color::Blue);
+}
+
+#[test]
+fn customise_palette() {
+    let mut palette = Palette::default();
+    palette.set("glob", "dark_cyan").unwrap();
+    palette.set("search", "#ebcb8b").unwrap();
+    palette.set("muted", "244").unwrap();
+    assert_eq!(palette.glob, Color::DarkCyan);
+    assert_eq!(
+        palette.search,
+        Color::Rgb {
+            r: 0xeb,
+            g: 0xcb,
+            b: 0x8b
+        }
+    );
+    assert_eq!(palette.muted, Color::AnsiValue(244));
+    assert_eq!(
+        palette.convert(ColorDepth::Ansi256).search,
+        Color::AnsiValue(222)
+    );
+    assert!(palette.set("prompt", "blue").is_err());
+    assert!(palette.set("glob", "#12345").is_err());
+    assert!(palette.set("glob", "ultraviolet").is_err());
+}
+
+#[test]
+fn choose_color_mode() {
+    assert_eq!("never".parse(), Ok(ColorMode::Never));
+    assert!("sometimes".parse::<ColorMode>().is_err());
+    assert!(ColorMode::Auto.colored_with(true, false));
+    assert!(!ColorMode::Auto.colored_with(false, false));
+    assert!(!ColorMode::Auto.colored_with(true, true));
+    assert!(ColorMode::Always.colored_with(false, true));
+    assert!(!ColorMode::Never.colored_with(true, false));
+}